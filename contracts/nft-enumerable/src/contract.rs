@@ -1,134 +1,3513 @@
 // SPDX-License-Identifier: MIT
 // Compatible with OpenZeppelin Stellar Soroban Contracts ^0.4.1
 
+//! # Events
+//!
+//! Lifecycle events take the loan id and the loan's action nonce as their
+//! last topics, so indexers can subscribe to a single loan. The nonce starts
+//! at 1 and grows by one with every event about the loan, so consumers can
+//! drop duplicates and spot gaps:
+//!
+//! | Topics                                          | Data        |
+//! |-------------------------------------------------|-------------|
+//! | `("loan", "created", loan_id, nonce)`           | `Loan`      |
+//! | `("loan", "repayment", loan_id, nonce)`         | `Repayment` |
+//! | `("loan", "repaid", loan_id, nonce)`            | `Loan`      |
+//! | `("loan", "refinanced", loan_id, nonce)`        | `Loan`      |
+//! | `("loan", "lender_assigned", loan_id, nonce)`   | `Loan`      |
+//! | `("loan", "defaulted", loan_id, nonce)`         | `Loan`      |
+//! | `("loan", "called", loan_id, nonce)`            | `Loan`      |
+//! | `("auction", "started", loan_id, nonce)`        | `Auction`   |
+//! | `("auction", "bid", loan_id, nonce)`            | `Auction`   |
+//! | `("loan", "liquidated", loan_id, nonce)`        | `Loan`      |
+//! | `("collateral", "added", loan_id, nonce)`       | `Loan`      |
+//! | `("collateral", "reclaimed", loan_id, nonce)`   | `Loan`      |
+//!
+//! `Loan` and `Auction` payloads are the records as stored after the change.
+//! Owner actions publish events without a loan nonce:
+//!
+//! | Topics                         | Data                                |
+//! |--------------------------------|-------------------------------------|
+//! | `("admin", "max_ltv")`         | new max LTV in bps                  |
+//! | `("admin", "force_closed")`    | `(loan_id, final_status, reason)`   |
+//! | `("emergency", "nft")`         | `(token_id, to)`                    |
+//! | `("emergency", "token")`       | `(token, amount, to)`               |
+//!
+//! When the circuit breaker pauses lending it publishes
+//! `("circuit", "tripped")` with the number of liquidations in the trailing
+//! hour.
+//!
 //! # Security
 //!
+//! The Soroban host rejects any call back into a contract already on the
+//! call stack, so a malicious token or collection can't reenter the
+//! contract mid-operation and no reentrancy guard is kept.
+//!
 //! For security issues, please contact: security@example.com
 #![no_std]
 
-use soroban_sdk::{Address, contract, contractimpl, Env, String, symbol_short, panic_with_error, Error};
-use stellar_contract_utils::pausable::{self as pausable, Pausable};
-use stellar_macros::{default_impl, when_not_paused};
-use stellar_tokens::non_fungible::{
-    Base, burnable::NonFungibleBurnable, enumerable::{NonFungibleEnumerable, Enumerable},
-    NonFungibleToken
-};
+use soroban_sdk::{
+    Address, BytesN, contract, contractclient, contracterror, contractimpl, contracttype, Env,
+    IntoVal, String, Symbol, symbol_short, panic_with_error, token::TokenClient, Val, Vec
+};
+use stellar_contract_utils::pausable::{self as pausable, Pausable};
+use stellar_macros::{default_impl, when_not_paused, when_paused};
+use stellar_tokens::non_fungible::{
+    Base, burnable::NonFungibleBurnable, emit_transfer,
+    enumerable::{NonFungibleEnumerable, Enumerable}, NFTStorageKey, NonFungibleToken
+};
+
+/// Denominator for values expressed in basis points.
+const MAX_BPS: u32 = 10_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+const DAYS_PER_YEAR: u64 = 365;
+/// Largest page returned by paginated queries.
+const MAX_PAGE_SIZE: u32 = 100;
+/// Most payments kept in a loan's payment history; older ones are dropped.
+const MAX_PAYMENT_HISTORY: u32 = 100;
+/// Ledgers a bumped loan stays live for unless the owner sets otherwise,
+/// about 30 days at 5 second ledgers.
+const DEFAULT_LOAN_TTL: u32 = 518_400;
+/// Time a liquidation auction stays open for bids, in seconds.
+const AUCTION_DURATION: u64 = SECONDS_PER_DAY;
+/// Trailing window, in seconds, over which the circuit breaker counts
+/// liquidations.
+const CIRCUIT_BREAKER_WINDOW: u64 = 60 * 60;
+/// Version of the storage layout this code reads and writes. Deployments
+/// from before versioning, which kept a single loan in instance storage,
+/// are version 0.
+const SCHEMA_VERSION: u32 = 1;
+/// Interest rate to pass when creating a loan to take the protocol's
+/// current default rate.
+pub const USE_DEFAULT_RATE: u32 = u32::MAX;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum LendingNFTError {
+    OwnerNotSet = 1,
+    Unauthorized = 2,
+    NotTokenOwner = 3,
+    AlreadyCollateral = 4,
+    NotBorrower = 6,
+    LoanNotActive = 7,
+    LoanNotFound = 8,
+    InvalidBps = 9,
+    OracleNotConfigured = 10,
+    LtvExceeded = 11,
+    NoYieldSource = 12,
+    /// The configured oracle could not be queried for a usable price
+    OracleUnavailable = 13,
+    PaymentTokenNotSet = 14,
+    NotLiquidatable = 15,
+    NotLiquidating = 16,
+    AuctionClosed = 17,
+    AuctionOpen = 18,
+    BidTooLow = 19,
+    NoBids = 20,
+    AuctionHasBids = 21,
+    /// The loan would take the protocol past its maximum TVL
+    TvlExceeded = 22,
+    CollectionNotAllowed = 23,
+    /// The token backs an open loan and can't be moved
+    CollateralLocked = 24,
+    /// The migration doesn't start from the version storage is at
+    MigrationVersionMismatch = 25,
+    ArithmeticOverflow = 26,
+    /// Loan and repayment amounts must be positive
+    InvalidAmount = 27,
+    LendingPaused = 28,
+    /// The loan's collateral was already seized or sold
+    AlreadyLiquidated = 29,
+    /// The loan is not behind on its repayments
+    NotInDefault = 30,
+    /// The loan is larger than a single NFT may back
+    LoanPerNftExceeded = 31,
+    /// The protocol has created as many loans as it is allowed to
+    LoanCapReached = 32,
+    NotRepaid = 33,
+    /// The collateral is no longer held by the contract
+    AlreadyReclaimed = 34,
+    /// The loan's rate is above the most the borrower accepted
+    RateTooHigh = 35,
+    /// The borrower already has as many open loans as they may hold
+    TooManyActiveLoans = 36,
+    /// The collateral of a repaid loan is still within its release delay
+    CollateralNotReleased = 37,
+    /// Interest compounding is not enabled
+    CompoundingDisabled = 38,
+    /// The loan is still active, defaulted or being liquidated
+    LoanStillOpen = 39,
+    /// The repaid loan's collateral must be reclaimed first
+    CollateralNotReclaimed = 40,
+    /// A loan asked for the default interest rate but none is set
+    DefaultRateNotSet = 41,
+    /// The transaction executed after the deadline the borrower set
+    DeadlinePassed = 42,
+    /// Only open-term loans can be called
+    NotOpenTerm = 43,
+    /// The loan has already been called
+    AlreadyCalled = 44,
+    /// The loan's duration is longer than the protocol allows
+    DurationTooLong = 45,
+    /// A pool loan was requested but no rate model is set
+    RateModelNotSet = 46,
+    /// The rate model's base rate is above its max rate
+    InvalidRateModel = 47,
+    /// The pool doesn't have enough unborrowed supply for the loan
+    InsufficientLiquidity = 48,
+    /// The lender doesn't hold that many pool shares
+    InsufficientShares = 49,
+    /// The loan is smaller than the protocol's minimum
+    LoanTooSmall = 50,
+    /// A loan can only be force-closed as repaid or liquidated
+    InvalidFinalStatus = 51,
+    /// The token was liquidated too recently to back a new loan
+    RecollateralizeCooldown = 52,
+    /// A partial repayment is below the protocol's minimum
+    RepaymentTooSmall = 53,
+    /// An interest-only period cannot outlast the loan
+    InterestOnlyTooLong = 54,
+    /// A projection was asked for a time that has already passed
+    TimestampInPast = 55,
+    /// The loan is past its grace period and can no longer be refinanced
+    PastGracePeriod = 56,
+    /// A pool loan's collateral can't be handed to the pool, only sold
+    PoolCannotHoldCollateral = 57,
+    /// The withdrawal would take payment tokens held for the pool, bidders
+    /// or protocol fees
+    FundsReserved = 58,
+}
+
+/// Lifecycle state of a loan.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoanStatus {
+    Active,
+    /// Behind on its repayments; still owed and open to liquidation
+    Defaulted,
+    Repaid,
+    /// Collateral is being auctioned to cover the loan
+    Liquidating,
+    Liquidated,
+}
+
+/// How a loan's due date is set.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LoanKind {
+    /// Due `duration_days` after creation
+    FixedTerm,
+    /// Due `duration_days` after the lender calls it, and never before
+    OpenTerm,
+}
+
+/// A loan backed by an NFT held as collateral.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Loan {
+    pub borrower: Address,
+    /// Account owed the loan's debt
+    pub lender: Address,
+    /// NFT contract the collateral belongs to
+    pub collection: Address,
+    pub token_id: u32,
+    /// Value of the collateral when the loan was created
+    pub collateral_value: i128,
+    pub amount: i128,
+    /// Annual interest rate in basis points, where 10000 is 100%
+    pub interest_rate: u32,
+    pub duration_days: u32,
+    pub created_at: u64,
+    /// Number of equal installments the loan is repaid in, 0 if unscheduled
+    pub installments: u32,
+    /// Days from creation during which only interest is due, 0 if none
+    pub interest_only_days: u32,
+    pub status: LoanStatus,
+    /// Total amount paid towards the loan
+    pub repaid: i128,
+    /// Liquidation penalty fixed at creation, in basis points
+    pub liquidation_penalty_bps: u32,
+    /// Annual rate accrued past the due date, in basis points
+    pub late_fee_rate: u32,
+    /// Fees charged on the loan: the origination fee and any liquidation
+    /// penalty
+    pub fees: i128,
+    pub fees_paid: i128,
+    pub interest_paid: i128,
+    pub principal_paid: i128,
+    /// Account that received the collateral when the loan was liquidated
+    pub liquidator: Option<Address>,
+    /// Timestamp interest was last compounded at, or the creation time
+    pub last_accrual_timestamp: u64,
+    /// Interest accrued up to `last_accrual_timestamp`, paid or not
+    pub accrued_interest: i128,
+    pub kind: LoanKind,
+    /// Timestamp an open-term loan was called at
+    pub called_at: Option<u64>,
+    /// Further tokens of the same collection added to the collateral
+    pub extra_collateral: Vec<u32>,
+}
+
+/// Conditions a borrower attaches to a new loan, reverting its creation if
+/// they don't hold.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanGuards {
+    /// Highest rate, after any discount, the loan may be created at
+    pub max_acceptable_rate: Option<u32>,
+    /// Latest timestamp the loan may be created at
+    pub deadline: u64,
+}
+
+/// Terms a loan was originally created with. Kept unchanged for the life
+/// of the loan, whatever later changes are made to it.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanTerms {
+    pub borrower: Address,
+    pub token_id: u32,
+    pub amount: i128,
+    pub interest_rate: u32,
+    pub duration_days: u32,
+    pub created_at: u64,
+    /// Origination fee charged on the loan
+    pub fees: i128,
+}
+
+/// Point-in-time view of a loan together with its computed balances.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanDetail {
+    pub loan_id: u32,
+    pub loan: Loan,
+    pub accrued_interest: i128,
+    pub outstanding_balance: i128,
+    /// Timestamp at which the loan falls due
+    pub due_at: u64,
+    /// Fees charged on the loan so far, including any liquidation penalty
+    pub total_fees_charged: i128,
+    /// Account that received the collateral, once the loan is liquidated
+    pub liquidator: Option<Address>,
+}
+
+/// Everything a UI shows about a loan, read in one call.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanSummary {
+    pub loan: Loan,
+    pub accrued_interest: i128,
+    pub outstanding_balance: i128,
+    /// Timestamp at which the loan falls due
+    pub due_at: u64,
+    /// Whether the loan can be liquidated right now
+    pub liquidatable: bool,
+}
+
+/// Auction of a defaulted loan's collateral.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Auction {
+    /// Debt owed on the loan when the auction started
+    pub debt: i128,
+    /// Timestamp after which no more bids are accepted
+    pub ends_at: u64,
+    pub bidder: Option<Address>,
+    /// Highest bid, held in escrow until the auction is settled
+    pub bid: i128,
+}
+
+/// Aggregate lending stats for one collection.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CollectionStats {
+    /// Loans not yet repaid or liquidated
+    pub active_loans: u32,
+    /// Principal outstanding on those loans
+    pub tvl: i128,
+    /// Value of their collateral when the loans were created
+    pub collateral_value: i128,
+    /// Principal outstanding over collateral value, in basis points
+    pub average_ltv_bps: u32,
+}
+
+/// How a borrower's past loans ended.
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BorrowerStats {
+    /// Loans repaid in full
+    pub repaid: u32,
+    /// Loans marked defaulted by their lender
+    pub defaulted: u32,
+    /// Loans whose collateral was seized or sold
+    pub liquidated: u32,
+}
+
+/// Protocol-wide lending stats.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractStats {
+    /// Loans ever created, counting closed ones
+    pub total_loans: u32,
+    /// Loans still owed: active or defaulted
+    pub active_loans: u32,
+    /// Principal outstanding across open loans
+    pub total_outstanding: i128,
+    /// Tokens locked as collateral, including those being auctioned
+    pub collateral_locked: u32,
+    /// Loans ever liquidated
+    pub liquidations: u32,
+}
+
+/// How collateral is valued when the oracle reports a zero or missing
+/// price.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PriceFallback {
+    /// Revert with `OracleUnavailable`
+    Revert,
+    /// Use the last usable price the oracle reported for the token
+    LastKnown,
+    /// Use the value set by the owner for the token
+    Static,
+}
+
+/// Interest rates of pool-funded loans, interpolated linearly between
+/// `base_rate` at no utilization and `max_rate` when the pool is fully lent
+/// out. Both are annual rates in basis points.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateModel {
+    pub base_rate: u32,
+    pub max_rate: u32,
+}
+
+/// Snapshot of the operational flags that gate contract actions.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OperationalStatus {
+    /// Global pause of minting, transfers and burns
+    pub paused: bool,
+    /// Pause of new loan origination only
+    pub lending_paused: bool,
+    /// Whether new loans can currently be opened
+    pub borrowing_enabled: bool,
+    /// Whether interest is currently stopped by a freeze
+    pub interest_frozen: bool,
+}
+
+/// Allocation of a repayment across the components of a loan's debt.
+/// Payments settle fees first, then accrued interest, then principal.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Repayment {
+    pub fees: i128,
+    pub interest: i128,
+    pub principal: i128,
+}
+
+/// Contract pricing tokens for use as collateral.
+#[contractclient(name = "OracleClient")]
+pub trait Oracle {
+    /// Get the current price of `token_id`.
+    fn price(e: Env, token_id: u32) -> i128;
+}
+
+/// NFT contract whose tokens can be posted as collateral.
+#[contractclient(name = "CollectionClient")]
+pub trait Collection {
+    fn owner_of(e: Env, token_id: u32) -> Address;
+    fn transfer(e: Env, from: Address, to: Address, token_id: u32);
+    fn transfer_from(e: Env, spender: Address, from: Address, to: Address, token_id: u32);
+}
+
+/// Contract paying out the yield earned by a yield-bearing token.
+#[contractclient(name = "YieldSourceClient")]
+pub trait YieldSource {
+    /// Claim the yield accrued by `token_id` since the last claim, paying
+    /// it to `to` in the lending contract's payment token and returning the
+    /// amount claimed.
+    fn claim_yield(e: Env, token_id: u32, to: Address) -> i128;
+}
+
+#[contract]
+pub struct LendingNFT;
+
+#[contractimpl]
+impl LendingNFT {
+    pub fn __constructor(
+        e: &Env,
+        owner: Address,
+        max_ltv_bps: u32,
+        max_loan_per_nft: i128,
+        min_loan_amount: i128,
+        liquidation_bonus_bps: u32,
+        payment_token: Address,
+    ) {
+        if max_ltv_bps > MAX_BPS || liquidation_bonus_bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        if min_loan_amount < 0 {
+            panic_with_error!(e, LendingNFTError::InvalidAmount);
+        }
+        let uri = String::from_str(e, "www.lendingnft.com");
+        let name = String::from_str(e, "LendingNFT");
+        let symbol = String::from_str(e, "LNF");
+        Base::set_metadata(e, uri, name, symbol);
+        e.storage().instance().set(&symbol_short!("owner"), &owner);
+        e.storage().instance().set(&symbol_short!("max_ltv"), &max_ltv_bps);
+        e.storage().instance().set(&symbol_short!("nft_cap"), &max_loan_per_nft);
+        e.storage().instance().set(&symbol_short!("min_loan"), &min_loan_amount);
+        e.storage().instance().set(&symbol_short!("liq_bonus"), &liquidation_bonus_bps);
+        e.storage().instance().set(&symbol_short!("pay_token"), &payment_token);
+        e.storage().instance().set(&symbol_short!("version"), &SCHEMA_VERSION);
+    }
+
+    /// Replace the contract's wasm. Call `post_upgrade_migrate` afterwards
+    /// to bring storage up to the new code's layout.
+    pub fn upgrade(e: &Env, caller: Address, wasm_hash: BytesN<32>) {
+        Self::require_owner(e, &caller);
+        e.deployer().update_current_contract_wasm(wasm_hash);
+    }
+
+    /// Migrate storage from `from_version` to the layout of the running code,
+    /// returning whether any migration ran. Once storage is up to date,
+    /// further calls do nothing.
+    pub fn post_upgrade_migrate(e: &Env, caller: Address, from_version: u32) -> bool {
+        Self::require_owner(e, &caller);
+        let version = Self::schema_version(e);
+        if version >= SCHEMA_VERSION || from_version < version {
+            return false;
+        }
+        if from_version != version {
+            panic_with_error!(e, LendingNFTError::MigrationVersionMismatch);
+        }
+        for step in version..SCHEMA_VERSION {
+            Self::migrate_from(e, step);
+        }
+        e.storage().instance().set(&symbol_short!("version"), &SCHEMA_VERSION);
+        true
+    }
+
+    /// Get the version of the storage layout
+    pub fn schema_version(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("version")).unwrap_or(0)
+    }
+
+    /// Get the version of the running code, which is the storage layout it
+    /// migrates to
+    pub fn version(_e: &Env) -> u32 {
+        SCHEMA_VERSION
+    }
+
+    /// Get the optional modules currently enabled, so clients can adapt:
+    /// `auctions` always, `oracle` with an oracle set, `pools` with a pool
+    /// rate model set, `compound` with compounding enabled and `collections`
+    /// while external collections are allowed
+    pub fn features(e: &Env) -> Vec<Symbol> {
+        let mut features = Vec::from_array(e, [symbol_short!("auctions")]);
+        if Self::oracle(e).is_some() {
+            features.push_back(symbol_short!("oracle"));
+        }
+        if Self::rate_model(e).is_some() {
+            features.push_back(symbol_short!("pools"));
+        }
+        if Self::compounding_enabled(e) {
+            features.push_back(symbol_short!("compound"));
+        }
+        if !Self::allowed_collections(e).is_empty() {
+            features.push_back(Symbol::new(e, "collections"));
+        }
+        features
+    }
+
+    #[when_not_paused]
+    pub fn mint(e: &Env, to: Address, token_id: u32, caller: Address) {
+        let owner = e.storage().instance().get(&symbol_short!("owner"))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::OwnerNotSet));
+        if caller != owner {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        Enumerable::non_sequential_mint(e, &to, token_id);
+    }
+
+    /// Mint a token with the next unused id to `to`, returning the id. Ids
+    /// count up from 1, skipping any already taken through `mint`.
+    #[when_not_paused]
+    pub fn mint_next(e: &Env, to: Address, caller: Address) -> u32 {
+        Self::require_owner(e, &caller);
+        let mut token_id: u32 =
+            e.storage().instance().get(&symbol_short!("next_tok")).unwrap_or(1);
+        while e.storage().persistent().has(&NFTStorageKey::Owner(token_id)) {
+            token_id = token_id.checked_add(1)
+                .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        }
+        let next = token_id.checked_add(1)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        e.storage().instance().set(&symbol_short!("next_tok"), &next);
+        Enumerable::non_sequential_mint(e, &to, token_id);
+        token_id
+    }
+
+    // ===== LENDING FUNCTIONS =====
+
+    /// Create a loan using NFT as collateral. The NFT is escrowed in the
+    /// contract until the borrower reclaims it after repaying the loan, and
+    /// the principal is paid to the borrower by the lender, who must
+    /// authorize it. `caller` is the borrower or an operator they approved.
+    pub fn create_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        caller: Address
+    ) -> u32 {
+        Self::require_borrower_or_operator(e, &borrower, &caller);
+        Self::open_native_loan(e, borrower, token_id, amount, interest_rate, duration_days, 0)
+    }
+
+    /// Create a loan like `create_loan`, reverting if the rate it would be
+    /// created at, after any discount, is above `max_acceptable_rate`. This
+    /// guards the borrower against terms changing between quote and
+    /// execution. Shorthand for `create_loan_with_guards` with no deadline.
+    pub fn create_loan_with_max_rate(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        max_acceptable_rate: u32,
+    ) -> u32 {
+        let guards = LoanGuards {
+            max_acceptable_rate: Some(max_acceptable_rate),
+            deadline: u64::MAX,
+        };
+        Self::create_loan_with_guards(
+            e, borrower, token_id, amount, interest_rate, duration_days, guards,
+        )
+    }
+
+    /// Create a loan like `create_loan`, reverting unless `guards` hold. This
+    /// protects composed transactions executed later than signed, or against
+    /// a default rate that changed in between.
+    pub fn create_loan_with_guards(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        guards: LoanGuards,
+    ) -> u32 {
+        borrower.require_auth();
+        if e.ledger().timestamp() > guards.deadline {
+            panic_with_error!(e, LendingNFTError::DeadlinePassed);
+        }
+        let rate = Self::resolved_rate(e, &borrower, interest_rate);
+        if guards.max_acceptable_rate.is_some_and(|max| rate > max) {
+            panic_with_error!(e, LendingNFTError::RateTooHigh);
+        }
+        Self::open_native_loan(e, borrower, token_id, amount, interest_rate, duration_days, 0)
+    }
+
+    /// Create an open-term loan. It has no due date until the lender calls
+    /// it, after which the borrower has `repayment_window_days` to repay
+    /// before it can be liquidated.
+    pub fn create_open_term_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        repayment_window_days: u32,
+    ) -> u32 {
+        borrower.require_auth();
+        let (mut loan, value) =
+            Self::native_loan(e, borrower, token_id, amount, interest_rate, repayment_window_days);
+        loan.kind = LoanKind::OpenTerm;
+        Self::escrow_and_open(e, loan, value)
+    }
+
+    /// Create a loan funded by the lending pool rather than the owner, at the
+    /// pool's current `borrow_rate`. The principal is paid out of the pool to
+    /// the borrower. The contract is the loan's lender: repayments and
+    /// liquidation proceeds flow back to the pool, interest included.
+    pub fn create_pool_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        duration_days: u32,
+    ) -> u32 {
+        borrower.require_auth();
+        if amount > Self::pool_balance(e) {
+            panic_with_error!(e, LendingNFTError::InsufficientLiquidity);
+        }
+        let rate = Self::borrow_rate(e);
+        let (mut loan, value) =
+            Self::native_loan(e, borrower.clone(), token_id, amount, rate, duration_days);
+        loan.lender = e.current_contract_address();
+        Self::escrow_and_open(e, loan, value)
+    }
+
+    /// Call an open-term loan, starting its repayment window. Only the lender
+    /// may call a loan, and only once.
+    pub fn call_loan(e: &Env, loan_id: u32, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.lender != caller {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        if loan.kind != LoanKind::OpenTerm {
+            panic_with_error!(e, LendingNFTError::NotOpenTerm);
+        }
+        if loan.status != LoanStatus::Active {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        if loan.called_at.is_some() {
+            panic_with_error!(e, LendingNFTError::AlreadyCalled);
+        }
+
+        loan.called_at = Some(e.ledger().timestamp());
+        Self::save_loan(e, loan_id, &loan);
+        Self::publish_loan_event(e, symbol_short!("called"), loan_id, &loan);
+    }
+
+    /// Create a loan repaid in `installments` equal installments due at even
+    /// intervals over its duration. Missing one lets the lender mark the
+    /// loan defaulted.
+    pub fn create_installment_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        installments: u32,
+    ) -> u32 {
+        borrower.require_auth();
+        Self::open_native_loan(
+            e, borrower, token_id, amount, interest_rate, duration_days, installments,
+        )
+    }
+
+    /// Create a loan where only interest is due for the first
+    /// `interest_only_days`, after which the principal falls due in full.
+    /// The loan cannot be marked defaulted during that window.
+    pub fn create_interest_only_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        interest_only_days: u32,
+    ) -> u32 {
+        borrower.require_auth();
+        if interest_only_days > duration_days {
+            panic_with_error!(e, LendingNFTError::InterestOnlyTooLong);
+        }
+        let (mut loan, value) =
+            Self::native_loan(e, borrower, token_id, amount, interest_rate, duration_days);
+        loan.interest_only_days = interest_only_days;
+        Self::escrow_and_open(e, loan, value)
+    }
+
+    /// Create a loan using as collateral an NFT from another contract in the
+    /// allowed collections. The NFT is escrowed in this contract until it is
+    /// reclaimed after repayment, so the borrower must have approved it for
+    /// transfer.
+    pub fn create_loan_with_collection(
+        e: &Env,
+        borrower: Address,
+        collection: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+    ) -> u32 {
+        borrower.require_auth();
+        if !Self::allowed_collections(e).contains(&collection) {
+            panic_with_error!(e, LendingNFTError::CollectionNotAllowed);
+        }
+        let nft = CollectionClient::new(e, &collection);
+        if nft.owner_of(&token_id) != borrower {
+            panic_with_error!(e, LendingNFTError::NotTokenOwner);
+        }
+        Self::check_cooldown(e, &collection, token_id);
+
+        let value = Self::external_collateral_value(e, collection.clone(), token_id);
+        Self::check_loan_limits(e, Self::weighted_value(e, &collection, value), amount);
+        let loan =
+            Self::new_loan(e, borrower, collection, token_id, amount, interest_rate, duration_days);
+        let contract = e.current_contract_address();
+        nft.transfer_from(&contract, &loan.borrower, &contract, &token_id);
+        Self::open_loan(e, loan, value)
+    }
+
+    /// Add another token of the loan's collection to an active loan's
+    /// collateral, raising the value backing it. The token is escrowed and
+    /// returned or seized together with the rest of the collateral.
+    pub fn add_collateral(e: &Env, loan_id: u32, token_id: u32, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.borrower != caller {
+            panic_with_error!(e, LendingNFTError::NotBorrower);
+        }
+        if loan.status != LoanStatus::Active {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        let key = (symbol_short!("collat"), loan.collection.clone(), token_id);
+        if e.storage().persistent().has(&key) {
+            panic_with_error!(e, LendingNFTError::AlreadyCollateral);
+        }
+
+        let contract = e.current_contract_address();
+        if loan.collection == contract {
+            if Base::owner_of(e, token_id) != caller {
+                panic_with_error!(e, LendingNFTError::NotTokenOwner);
+            }
+            Enumerable::transfer(e, &caller, &contract, token_id);
+        } else {
+            let nft = CollectionClient::new(e, &loan.collection);
+            if nft.owner_of(&token_id) != caller {
+                panic_with_error!(e, LendingNFTError::NotTokenOwner);
+            }
+            nft.transfer_from(&contract, &caller, &contract, &token_id);
+        }
+
+        let value = Self::token_value(e, &loan.collection, token_id);
+        Self::lock_token(e, loan_id, &loan.collection, token_id);
+        let (active_loans, tvl, total) = Self::collection_totals(e, &loan.collection);
+        let totals = (active_loans, tvl, Self::add(e, total, value));
+        Self::save_collection_totals(e, &loan.collection, totals);
+        loan.collateral_value = Self::add(e, loan.collateral_value, value);
+        loan.extra_collateral.push_back(token_id);
+        Self::save_loan(e, loan_id, &loan);
+        let scope = Symbol::new(e, "collateral");
+        Self::publish_event(e, scope, symbol_short!("added"), loan_id, loan);
+    }
+
+    /// Repay a loan, returning how the payment was allocated. Only up to the
+    /// outstanding balance is accepted; the rest of an overpayment is left
+    /// out of the allocation and the loan's records. `caller` is the
+    /// borrower or an operator they approved, and pays the fees applied to
+    /// the protocol and the rest to the lender, or into the pool for a pool
+    /// loan.
+    pub fn repay_loan(
+        e: &Env,
+        loan_id: u32,
+        amount: i128,
+        caller: Address
+    ) -> Repayment {
+        Self::require_positive(e, amount);
+        let mut loan = Self::load_loan(e, loan_id);
+        Self::require_borrower_or_operator(e, &loan.borrower, &caller);
+
+        // Payments to closed loans either revert or are refunded untouched
+        if !Self::is_open(&loan) {
+            if Self::refunds_closed_repayments(e) {
+                return Repayment { fees: 0, interest: 0, principal: 0 };
+            }
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+
+        Self::check_repayment(e, &loan, amount);
+        let repayment = Self::apply_payment(e, loan_id, &mut loan, amount);
+        Self::collect_repayment(e, &loan, &caller, &repayment);
+        repayment
+    }
+
+    /// Repay a loan on behalf of its borrower, who stays unchanged. The
+    /// payment is pulled from `payer`, who may be anyone, and is allocated,
+    /// capped and paid out as in `repay_loan`.
+    pub fn repay_loan_for(e: &Env, loan_id: u32, amount: i128, payer: Address) -> Repayment {
+        payer.require_auth();
+        Self::require_positive(e, amount);
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            if Self::refunds_closed_repayments(e) {
+                return Repayment { fees: 0, interest: 0, principal: 0 };
+            }
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+
+        Self::check_repayment(e, &loan, amount);
+        let repayment = Self::apply_payment(e, loan_id, &mut loan, amount);
+        Self::collect_repayment(e, &loan, &payer, &repayment);
+        repayment
+    }
+
+    /// Return the escrowed collateral of a repaid loan to its borrower
+    pub fn reclaim_collateral(e: &Env, loan_id: u32, token_id: u32, caller: Address) {
+        caller.require_auth();
+        let loan = Self::load_loan(e, loan_id);
+        if loan.borrower != caller {
+            panic_with_error!(e, LendingNFTError::NotBorrower);
+        }
+        if loan.token_id != token_id {
+            panic_with_error!(e, LendingNFTError::NotTokenOwner);
+        }
+        Self::release_collateral(e, loan_id, loan);
+    }
+
+    /// Return the escrowed collateral of a repaid loan to its borrower once
+    /// the release delay has passed. Anyone may trigger the release.
+    pub fn claim_released_collateral(e: &Env, loan_id: u32) {
+        Self::release_collateral(e, loan_id, Self::load_loan(e, loan_id));
+    }
+
+    /// Delete a repaid or liquidated loan to stop paying rent on its storage.
+    /// Its original terms are kept as a record. A repaid loan's collateral
+    /// must be reclaimed first. Only its borrower or lender may close it.
+    pub fn close_loan(e: &Env, loan_id: u32, caller: Address) {
+        caller.require_auth();
+        let loan = Self::load_loan(e, loan_id);
+        if caller != loan.borrower && caller != loan.lender {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        match loan.status {
+            LoanStatus::Repaid => {
+                if Self::collateral_holder(e, &loan) == e.current_contract_address() {
+                    panic_with_error!(e, LendingNFTError::CollateralNotReclaimed);
+                }
+            }
+            LoanStatus::Liquidated => {}
+            _ => panic_with_error!(e, LendingNFTError::LoanStillOpen),
+        }
+
+        Self::remove_from_status_list(e, loan.status, loan_id);
+        let mut ids = Self::get_user_loans(e, loan.borrower.clone());
+        if let Some(index) = ids.first_index_of(loan_id) {
+            ids.remove(index);
+            let key = (symbol_short!("user_loan"), loan.borrower.clone());
+            e.storage().persistent().set(&key, &ids);
+        }
+        let storage = e.storage().persistent();
+        storage.remove(&(symbol_short!("loan"), loan_id));
+        storage.remove(&(symbol_short!("auction"), loan_id));
+        storage.remove(&(symbol_short!("repaid_at"), loan_id));
+        storage.remove(&(symbol_short!("nonce"), loan_id));
+        storage.remove(&(symbol_short!("payments"), loan_id));
+    }
+
+    /// Claim the yield earned by a loan's collateral from its yield source
+    /// and apply it to the loan as a repayment. Only what the source actually
+    /// paid in is applied. Fees are kept as protocol fees, the rest goes to
+    /// the lender, or stays in the pool for a pool loan, and whatever exceeds
+    /// the debt goes to the borrower. Anyone may trigger a claim.
+    pub fn claim_collateral_yield(e: &Env, loan_id: u32) -> Repayment {
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        let source = Self::yield_source(e, loan.token_id)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::NoYieldSource));
+
+        let token = Self::payment_token_client(e);
+        let contract = e.current_contract_address();
+        let before = token.balance(&contract);
+        YieldSourceClient::new(e, &source).claim_yield(&loan.token_id, &contract);
+        let proceeds = token.balance(&contract) - before;
+        if proceeds <= 0 {
+            return Repayment { fees: 0, interest: 0, principal: 0 };
+        }
+
+        let repayment = Self::apply_payment(e, loan_id, &mut loan, proceeds);
+        // Fees stay in the contract as protocol fees
+        let to_lender = repayment.interest + repayment.principal;
+        if to_lender > 0 {
+            Self::pay_lender(e, &loan, to_lender);
+        }
+        let applied = repayment.fees + to_lender;
+        if proceeds > applied {
+            token.transfer(&contract, &loan.borrower, &(proceeds - applied));
+        }
+        repayment
+    }
+
+    /// Start auctioning the collateral of a loan past its due date. Anyone
+    /// may start an auction; bids are accepted for a day. Liquidations are
+    /// frozen while the contract is paused, but repayments are not, so
+    /// borrowers can always get out of debt.
+    #[when_not_paused]
+    pub fn start_liquidation(e: &Env, loan_id: u32) {
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_liquidatable(e, &loan) {
+            panic_with_error!(e, LendingNFTError::NotLiquidatable);
+        }
+        let auction = Auction {
+            debt: Self::balance_of(e, &loan),
+            ends_at: e.ledger().timestamp().saturating_add(AUCTION_DURATION),
+            bidder: None,
+            bid: 0,
+        };
+        e.storage().persistent().set(&(symbol_short!("auction"), loan_id), &auction);
+        Self::move_status(e, loan_id, &mut loan, LoanStatus::Liquidating);
+        Self::save_loan(e, loan_id, &loan);
+        let action = symbol_short!("started");
+        Self::publish_event(e, symbol_short!("auction"), action, loan_id, auction);
+    }
+
+    /// Bid `amount` of the payment token for a loan's collateral. The bid is
+    /// escrowed and must cover the debt and beat the current highest bid,
+    /// which is refunded.
+    pub fn bid(e: &Env, loan_id: u32, bidder: Address, amount: i128) {
+        bidder.require_auth();
+        let mut auction = Self::load_auction(e, loan_id);
+        if e.ledger().timestamp() >= auction.ends_at {
+            panic_with_error!(e, LendingNFTError::AuctionClosed);
+        }
+        if amount < auction.debt || amount <= auction.bid {
+            panic_with_error!(e, LendingNFTError::BidTooLow);
+        }
+
+        let token = Self::payment_token_client(e);
+        let contract = e.current_contract_address();
+        token.transfer(&bidder, &contract, &amount);
+        if let Some(previous) = &auction.bidder {
+            token.transfer(&contract, previous, &auction.bid);
+        }
+
+        auction.bidder = Some(bidder);
+        auction.bid = amount;
+        e.storage().persistent().set(&(symbol_short!("auction"), loan_id), &auction);
+        Self::publish_event(e, symbol_short!("auction"), symbol_short!("bid"), loan_id, auction);
+    }
+
+    /// Settle a finished auction: the collateral goes to the highest bidder,
+    /// the debt is paid to the lender, and the surplus, net of the
+    /// liquidation penalty, is returned to the borrower.
+    #[when_not_paused]
+    pub fn settle_liquidation(e: &Env, loan_id: u32) {
+        let mut loan = Self::load_loan(e, loan_id);
+        let auction = Self::load_auction(e, loan_id);
+        if e.ledger().timestamp() < auction.ends_at {
+            panic_with_error!(e, LendingNFTError::AuctionOpen);
+        }
+        let winner = auction.bidder
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::NoBids));
+
+        let token = Self::payment_token_client(e);
+        let contract = e.current_contract_address();
+        Self::pay_lender(e, &loan, auction.debt);
+        let refund = Self::settle_surplus(e, &mut loan, auction.bid - auction.debt);
+        if refund > 0 {
+            token.transfer(&contract, &loan.borrower, &refund);
+        }
+
+        Self::transfer_collateral(e, &loan, &winner);
+        Self::close_liquidation(e, loan_id, &mut loan, auction.debt, winner, true);
+    }
+
+    /// Seize the collateral of a loan whose auction ended without bids,
+    /// handing it to the lender. The lender or any registered keeper may
+    /// trigger it. The pool can't hold collateral, so a pool loan's keeper
+    /// buys it instead, as in `liquidate_with_bonus`, and the price is
+    /// credited back to the pool.
+    #[when_not_paused]
+    pub fn liquidate_loan(e: &Env, loan_id: u32, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.lender != caller && !Self::is_keeper(e, caller.clone()) {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        let auction = Self::load_auction(e, loan_id);
+        if e.ledger().timestamp() < auction.ends_at {
+            panic_with_error!(e, LendingNFTError::AuctionOpen);
+        }
+        if auction.bidder.is_some() {
+            panic_with_error!(e, LendingNFTError::AuctionHasBids);
+        }
+
+        if Self::is_pool_loan(e, &loan) {
+            Self::sell_collateral(e, loan_id, &mut loan, caller);
+        } else {
+            // Close the loan before moving the collateral so any later
+            // attempt sees it liquidated
+            let lender = loan.lender.clone();
+            Self::close_liquidation(e, loan_id, &mut loan, 0, lender.clone(), true);
+            Self::transfer_collateral(e, &loan, &lender);
+        }
+    }
+
+    /// Hand an open loan's collateral to the lender in settlement of its
+    /// debt, closing it as liquidated. Being voluntary, it may happen at any
+    /// time, before the loan is due or in default, and no liquidation bonus
+    /// or penalty applies. The pool can't hold collateral, so pool loans
+    /// can't be surrendered.
+    pub fn surrender_collateral(e: &Env, loan_id: u32, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.borrower != caller {
+            panic_with_error!(e, LendingNFTError::NotBorrower);
+        }
+        if !Self::is_open(&loan) {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        if Self::is_pool_loan(e, &loan) {
+            panic_with_error!(e, LendingNFTError::PoolCannotHoldCollateral);
+        }
+
+        let lender = loan.lender.clone();
+        Self::close_liquidation(e, loan_id, &mut loan, 0, lender.clone(), false);
+        Self::transfer_collateral(e, &loan, &lender);
+    }
+
+    /// Liquidate a loan past its due date, or defaulted, without an auction:
+    /// `liquidator` buys the collateral at its current value less the
+    /// liquidation bonus. The debt is paid to the lender out of the price,
+    /// and what is left, net of the liquidation penalty, is returned to the
+    /// borrower. A price short of the debt all goes to the lender.
+    #[when_not_paused]
+    pub fn liquidate_with_bonus(e: &Env, loan_id: u32, liquidator: Address) {
+        liquidator.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_liquidatable(e, &loan) {
+            panic_with_error!(e, LendingNFTError::NotLiquidatable);
+        }
+        Self::sell_collateral(e, loan_id, &mut loan, liquidator);
+    }
+
+    /// Get the liquidation auction of a loan, if one was started
+    pub fn auction(e: &Env, loan_id: u32) -> Option<Auction> {
+        e.storage().persistent().get(&(symbol_short!("auction"), loan_id))
+    }
+
+    /// Extend the duration of an active loan by `additional_days`
+    pub fn extend_loan(e: &Env, caller: Address, loan_id: u32, additional_days: u32) {
+        Self::require_owner(e, &caller);
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.status != LoanStatus::Active {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        loan.duration_days = loan.duration_days.checked_add(additional_days)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        Self::save_loan(e, loan_id, &loan);
+    }
+
+    /// Roll an active loan, including one past its due date but still within
+    /// its grace period, into a new term. Interest owed so far is added to
+    /// the principal and the new term starts now, backed by the same
+    /// collateral. The new rate is resolved as when creating a loan, and the
+    /// new principal must fit the same loan-to-value, per-NFT and TVL limits.
+    pub fn refinance_loan(
+        e: &Env,
+        loan_id: u32,
+        new_duration_days: u32,
+        new_interest_rate: u32,
+        caller: Address,
+    ) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.borrower != caller {
+            panic_with_error!(e, LendingNFTError::NotBorrower);
+        }
+        if loan.status != LoanStatus::Active {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        if Self::is_liquidatable(e, &loan) {
+            panic_with_error!(e, LendingNFTError::PastGracePeriod);
+        }
+
+        Self::check_duration(e, new_duration_days);
+        let new_interest_rate = Self::resolved_rate(e, &loan.borrower, new_interest_rate);
+
+        let interest = Self::interest_due(e, &loan);
+        let principal = Self::principal_due(&loan);
+        // Interest rolled into a pool loan is earned by the pool and lent
+        // straight back out
+        if Self::is_pool_loan(e, &loan) {
+            Self::adjust_pool_supply(e, interest);
+        }
+        Self::adjust_outstanding(e, &loan, -principal);
+        loan.amount = Self::add(e, principal, interest);
+        if Self::lending_paused(e) {
+            panic_with_error!(e, LendingNFTError::LendingPaused);
+        }
+        let value = Self::weighted_value(e, &loan.collection, Self::value_of(e, &loan));
+        Self::check_exposure(e, value, loan.amount);
+        loan.principal_paid = 0;
+        loan.interest_paid = 0;
+        loan.interest_rate = new_interest_rate;
+        loan.duration_days = new_duration_days;
+        loan.created_at = e.ledger().timestamp();
+        loan.called_at = None;
+        loan.last_accrual_timestamp = loan.created_at;
+        loan.accrued_interest = 0;
+        Self::adjust_outstanding(e, &loan, loan.amount);
+        Self::save_loan(e, loan_id, &loan);
+        Self::publish_loan_event(e, Symbol::new(e, "refinanced"), loan_id, &loan);
+    }
+
+    /// Transfer the lender's claim on an open loan to `new_lender`, who is
+    /// paid its debt and holds its liquidation rights from now on
+    pub fn assign_lender(e: &Env, loan_id: u32, new_lender: Address, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        if loan.lender != caller {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        if !Self::is_open(&loan) && loan.status != LoanStatus::Liquidating {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        loan.lender = new_lender;
+        Self::save_loan(e, loan_id, &loan);
+        Self::publish_loan_event(e, Symbol::new(e, "lender_assigned"), loan_id, &loan);
+    }
+
+    /// Get the due date and amount of a loan's next installment. Loans
+    /// without a schedule have a single installment of the whole balance at
+    /// their due date.
+    pub fn next_installment_due(e: &Env, loan_id: u32) -> (u64, i128) {
+        let loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        Self::next_installment(e, &loan)
+    }
+
+    /// Get the due date and amount of each of a loan's installments, which
+    /// together cover its principal, its interest over the full term and
+    /// its fees. Loans without a schedule have a single installment, and an
+    /// open-term loan not yet called owes its current balance, with no due
+    /// date.
+    pub fn repayment_schedule(e: &Env, loan_id: u32) -> Vec<(u64, i128)> {
+        let loan = Self::load_loan(e, loan_id);
+        let mut schedule = Vec::new(e);
+        if Self::awaiting_call(&loan) {
+            schedule.push_back((Self::due_at(&loan), Self::balance_of(e, &loan)));
+            return schedule;
+        }
+        let mut scheduled = 0;
+        for n in 1..=loan.installments.max(1) {
+            let (due, cumulative) = Self::installment(e, &loan, n);
+            schedule.push_back((due, cumulative - scheduled));
+            scheduled = cumulative;
+        }
+        schedule
+    }
+
+    /// Mark an active loan defaulted once an installment, or for unscheduled
+    /// loans the loan itself, is unpaid past its due date and the grace
+    /// period. Only the lender may do so, or for a pool loan any registered
+    /// keeper. Defaulted loans are open to liquidation.
+    pub fn mark_defaulted(e: &Env, loan_id: u32, caller: Address) {
+        caller.require_auth();
+        let mut loan = Self::load_loan(e, loan_id);
+        let keeps_pool = Self::is_pool_loan(e, &loan) && Self::is_keeper(e, caller.clone());
+        if loan.lender != caller && !keeps_pool {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+        if loan.status != LoanStatus::Active {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        let (due, _) = Self::next_installment(e, &loan);
+        if Self::in_interest_only_period(e, &loan)
+            || e.ledger().timestamp() <= due.saturating_add(Self::grace_period(e))
+        {
+            panic_with_error!(e, LendingNFTError::NotInDefault);
+        }
+        Self::move_status(e, loan_id, &mut loan, LoanStatus::Defaulted);
+        Self::save_loan(e, loan_id, &loan);
+        Self::record_outcome(e, &loan.borrower, LoanStatus::Defaulted);
+        Self::publish_loan_event(e, symbol_short!("defaulted"), loan_id, &loan);
+    }
+
+    /// Get loan information
+    pub fn get_loan_info(e: &Env, loan_id: u32) -> Loan {
+        Self::load_loan(e, loan_id)
+    }
+
+    /// Get the ids of loans with the given status, oldest first. At most
+    /// `limit` ids are returned, starting at position `start` in the list.
+    pub fn get_loans_by_status(e: &Env, status: LoanStatus, start: u32, limit: u32) -> Vec<u32> {
+        Self::page(e, &Self::status_list(e, status), start, limit)
+    }
+
+    /// Get the ids of loans created between `start` and `end`, both
+    /// inclusive, oldest first. Refinancing doesn't change when a loan was
+    /// created. At most `limit` ids are returned, capped to the maximum page
+    /// size.
+    pub fn loans_created_between(e: &Env, start: u64, end: u64, limit: u32) -> Vec<u32> {
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let next = Self::get_next_loan_id(e);
+        // Loans are numbered in creation order, so the first one created in
+        // the window is found by binary search rather than reading them all
+        let (mut low, mut high) = (1, next);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if Self::created_at(e, mid).is_some_and(|created_at| created_at >= start) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        let mut ids = Vec::new(e);
+        for loan_id in low..next {
+            if ids.len() >= limit {
+                break;
+            }
+            let Some(created_at) = Self::created_at(e, loan_id) else {
+                continue;
+            };
+            if created_at > end {
+                break;
+            }
+            ids.push_back(loan_id);
+        }
+        ids
+    }
+
+    /// Get the terms a loan was originally created with
+    pub fn original_terms(e: &Env, loan_id: u32) -> LoanTerms {
+        e.storage().persistent().get(&(symbol_short!("terms"), loan_id))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::LoanNotFound))
+    }
+
+    /// Get the ids of every loan taken by `user` that hasn't been closed
+    pub fn get_user_loans(e: &Env, user: Address) -> Vec<u32> {
+        e.storage().persistent().get(&(symbol_short!("user_loan"), user))
+            .unwrap_or_else(|| Vec::new(e))
+    }
+
+    /// Get a page of the loans taken by `user`. At most `limit` ids are
+    /// returned, capped to the maximum page size, starting at `start`.
+    pub fn get_user_loans_paged(e: &Env, user: Address, start: u32, limit: u32) -> Vec<u32> {
+        Self::page(e, &Self::get_user_loans(e, user), start, limit)
+    }
+
+    /// Get the number of loans taken by `user`
+    pub fn user_loan_count(e: &Env, user: Address) -> u32 {
+        Self::get_user_loans(e, user).len()
+    }
+
+    /// Get the nonce of the latest event published for a loan. Every event
+    /// about a loan increments it, so consumers can drop duplicates.
+    pub fn action_nonce(e: &Env, loan_id: u32) -> u32 {
+        e.storage().persistent().get(&(symbol_short!("nonce"), loan_id)).unwrap_or(0)
+    }
+
+    /// Get the id the next loan will be created with
+    pub fn next_loan_id(e: &Env) -> u32 {
+        Self::get_next_loan_id(e)
+    }
+
+    /// Get the number of loans ever created, counting closed ones
+    pub fn total_loans(e: &Env) -> u32 {
+        Self::get_next_loan_id(e) - 1
+    }
+
+    /// Get the payments made towards a loan as `(timestamp, amount)`, oldest
+    /// first. Only the latest `MAX_PAYMENT_HISTORY` are kept.
+    pub fn get_payments(e: &Env, loan_id: u32) -> Vec<(u64, i128)> {
+        e.storage().persistent().get(&(symbol_short!("payments"), loan_id))
+            .unwrap_or_else(|| Vec::new(e))
+    }
+
+    /// Get a loan together with its balances, due date and whether it can be
+    /// liquidated right now
+    pub fn get_loan_summary(e: &Env, loan_id: u32) -> LoanSummary {
+        let loan = Self::load_loan(e, loan_id);
+        LoanSummary {
+            accrued_interest: Self::accrued_interest(e, &loan),
+            outstanding_balance: Self::balance_of(e, &loan),
+            due_at: Self::due_at(&loan),
+            liquidatable: Self::is_liquidatable(e, &loan),
+            loan,
+        }
+    }
+
+    /// Extend how long a loan's record stays live to the configured target,
+    /// keeping it from being archived. Anyone may pay the rent for it.
+    pub fn bump_loan_ttl(e: &Env, loan_id: u32) {
+        let key = (symbol_short!("loan"), loan_id);
+        if !e.storage().persistent().has(&key) {
+            panic_with_error!(e, LendingNFTError::LoanNotFound);
+        }
+        let target = Self::loan_ttl(e).min(e.storage().max_ttl());
+        e.storage().persistent().extend_ttl(&key, target, target);
+    }
+
+    /// Get a loan together with its current balances
+    pub fn loan_detail(e: &Env, loan_id: u32) -> LoanDetail {
+        Self::detail_of(e, loan_id, Self::load_loan(e, loan_id))
+    }
+
+    /// Get the details of the loans that can be liquidated right now among
+    /// the open loans at positions `start..start + limit`, counting the
+    /// active loans first and then the defaulted ones.
+    pub fn liquidatable_loans_detailed(e: &Env, start: u32, limit: u32) -> Vec<LoanDetail> {
+        let mut open = Self::status_list(e, LoanStatus::Active);
+        open.append(&Self::status_list(e, LoanStatus::Defaulted));
+        let mut details = Vec::new(e);
+        for loan_id in Self::page(e, &open, start, limit) {
+            let loan = Self::load_loan(e, loan_id);
+            if Self::is_liquidatable(e, &loan) {
+                details.push_back(Self::detail_of(e, loan_id, loan));
+            }
+        }
+        details
+    }
+
+    /// Get the whole days left until a loan is due, rounded down, so it is
+    /// negative once the loan is overdue. Loans no longer open report 0.
+    pub fn days_remaining(e: &Env, loan_id: u32) -> i64 {
+        let loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            return 0;
+        }
+        let seconds = Self::due_at(&loan) as i128 - e.ledger().timestamp() as i128;
+        seconds.div_euclid(SECONDS_PER_DAY as i128) as i64
+    }
+
+    /// Check whether `caller` can liquidate a loan right now, without
+    /// changing anything. Liquidations are frozen while paused. An open loan
+    /// past its due date and grace period, or defaulted, can be liquidated by
+    /// anyone, through an auction or `liquidate_with_bonus`. A loan whose
+    /// auction ended without bids can only be seized by its lender or a
+    /// keeper.
+    pub fn can_liquidate(e: &Env, loan_id: u32, caller: Address) -> bool {
+        if pausable::paused(e) {
+            return false;
+        }
+        let loan = Self::load_loan(e, loan_id);
+        if loan.status != LoanStatus::Liquidating {
+            return Self::is_liquidatable(e, &loan);
+        }
+        let Some(auction) = Self::auction(e, loan_id) else {
+            return false;
+        };
+        e.ledger().timestamp() >= auction.ends_at
+            && auction.bidder.is_none()
+            && (loan.lender == caller || Self::is_keeper(e, caller))
+    }
+
+    /// Get the first timestamp at which a loan can be liquidated: the second
+    /// after its due date and grace period have passed. Defaulted loans can
+    /// be liquidated already, so the current time is returned for them, and
+    /// `u64::MAX` for loans no longer open, or uncalled open-term loans.
+    pub fn liquidatable_at(e: &Env, loan_id: u32) -> u64 {
+        let loan = Self::load_loan(e, loan_id);
+        match loan.status {
+            LoanStatus::Active => Self::due_at(&loan)
+                .saturating_add(Self::grace_period(e))
+                .saturating_add(1),
+            LoanStatus::Defaulted => e.ledger().timestamp(),
+            _ => u64::MAX,
+        }
+    }
+
+    /// Check whether any of `user`'s loans can be liquidated right now
+    pub fn has_liquidatable_loan(e: &Env, user: Address) -> bool {
+        Self::get_user_loans(e, user)
+            .iter()
+            .any(|loan_id| Self::is_liquidatable(e, &Self::load_loan(e, loan_id)))
+    }
+
+    /// Get how far a loan is from liquidation, scaled so that 10000 is full
+    /// health. Once the loan is due it drops to 0. Closed loans report full
+    /// health.
+    ///
+    /// With an oracle configured it is the collateral's value at the maximum
+    /// loan-to-value over the outstanding balance, so a loan at the limit
+    /// reports 10000. Otherwise it is the share of the loan's duration still
+    /// remaining.
+    pub fn health_factor(e: &Env, loan_id: u32) -> u32 {
+        let loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            return MAX_BPS;
+        }
+        let term = Self::term(&loan);
+        let remaining = Self::due_at(&loan).saturating_sub(e.ledger().timestamp());
+        if term == 0 || remaining == 0 || loan.status == LoanStatus::Defaulted {
+            return 0;
+        }
+        if Self::oracle(e).is_some() {
+            let debt = Self::balance_of(e, &loan);
+            if debt <= 0 {
+                return MAX_BPS;
+            }
+            let value = Self::weighted_value(e, &loan.collection, Self::value_of(e, &loan));
+            let borrowable = Self::mul(e, value, Self::max_ltv(e) as i128);
+            return (borrowable / debt).clamp(0, u32::MAX as i128) as u32;
+        }
+        (remaining as u128 * MAX_BPS as u128 / term as u128) as u32
+    }
+
+    /// Get how much more collateral value a loan needs for its outstanding
+    /// balance to be back within the maximum loan-to-value, after its
+    /// collection's collateral factor, or 0 if it already is. Closed loans
+    /// need nothing.
+    pub fn collateral_deficit(e: &Env, loan_id: u32) -> i128 {
+        let loan = Self::load_loan(e, loan_id);
+        let debt = Self::balance_of(e, &loan);
+        if !Self::is_open(&loan) || debt <= 0 {
+            return 0;
+        }
+        let factor = Self::collateral_factor(e, loan.collection.clone()) as i128;
+        let weight = Self::max_ltv(e) as i128 * factor;
+        if weight == 0 {
+            return i128::MAX;
+        }
+        let scaled = Self::mul(e, Self::mul(e, debt, MAX_BPS as i128), MAX_BPS as i128);
+        let required = Self::add(e, scaled, weight - 1) / weight;
+        Self::sub(e, required, Self::value_of(e, &loan)).max(0)
+    }
+
+    /// Calculate the interest accrued on a loan so far.
+    ///
+    /// Interest is simple and both rates are annual rates in basis points.
+    /// Time within the loan's duration accrues at `interest_rate`; time past
+    /// the due date accrues at the late fee rate fixed at loan creation.
+    pub fn calculate_interest(e: &Env, loan_id: u32) -> i128 {
+        Self::accrued_interest(e, &Self::load_loan(e, loan_id))
+    }
+
+    /// Preview the total due at maturity on a loan of `amount`: the principal
+    /// plus interest over the full duration, accrued as `calculate_interest`
+    /// does. Fees are not included.
+    pub fn quote_loan(e: &Env, amount: i128, interest_rate: u32, duration_days: u32) -> i128 {
+        let term = duration_days as u64 * SECONDS_PER_DAY;
+        Self::add(e, amount, Self::simple_interest(e, amount, interest_rate, term))
+    }
+
+    /// Compound the interest a loan accrued since it was last compounded,
+    /// returning the interest accrued so far. From then on, interest accrues
+    /// on the principal plus the interest still unpaid.
+    ///
+    /// Interest compounds once per call, so the compounding period is how
+    /// often `accrue` is called: a keeper calling it daily compounds daily.
+    /// Without calls, loans accrue simple interest. Anyone may call it once
+    /// the owner enables compounding.
+    pub fn accrue(e: &Env, loan_id: u32) -> i128 {
+        if !Self::compounding_enabled(e) {
+            panic_with_error!(e, LendingNFTError::CompoundingDisabled);
+        }
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        loan.accrued_interest = Self::accrued_interest(e, &loan);
+        loan.last_accrual_timestamp = e.ledger().timestamp();
+        Self::save_loan(e, loan_id, &loan);
+        loan.accrued_interest
+    }
+
+    /// Get the amount still needed to fully repay a loan: unpaid fees,
+    /// accrued interest net of any early-repayment discount, and principal.
+    /// During an interest-only period the principal is not yet due and is
+    /// left out. Closed loans owe nothing.
+    pub fn outstanding_balance(e: &Env, loan_id: u32) -> i128 {
+        let loan = Self::load_loan(e, loan_id);
+        if Self::is_open(&loan) && Self::in_interest_only_period(e, &loan) {
+            return Self::add(e, Self::fees_due(&loan), Self::interest_due(e, &loan));
+        }
+        Self::balance_of(e, &loan)
+    }
+
+    /// Project the amount needed to fully repay a loan at `at_timestamp`,
+    /// accruing interest and any late fees until then as if nothing is paid
+    /// in between. Closed loans owe nothing.
+    pub fn payoff_at(e: &Env, loan_id: u32, at_timestamp: u64) -> i128 {
+        if at_timestamp < e.ledger().timestamp() {
+            panic_with_error!(e, LendingNFTError::TimestampInPast);
+        }
+        let loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) {
+            return 0;
+        }
+        let interest = Self::interest_due_at(e, &loan, at_timestamp);
+        let due = Self::add(e, Self::fees_due(&loan), interest);
+        Self::add(e, due, Self::principal_due(&loan))
+    }
+
+    /// Get the principal outstanding across every open loan. Only principal
+    /// is counted: repayments reduce it by the share that went to principal,
+    /// and closing a loan, whether repaid or liquidated, removes what was left
+    /// of it. Accrued interest and fees are never included.
+    pub fn total_outstanding(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("tvl")).unwrap_or(0)
+    }
+
+    /// Get the interest `lender` has been paid across the loans it funded.
+    /// Interest paid on pool loans accrues to the pool's shares instead.
+    pub fn lender_interest_earned(e: &Env, lender: Address) -> i128 {
+        e.storage().persistent().get(&(symbol_short!("int_earn"), lender)).unwrap_or(0)
+    }
+
+    /// Get the mean interest rate of open loans in basis points, weighted by
+    /// the principal outstanding on each, 0 if nothing is outstanding
+    pub fn weighted_avg_rate(e: &Env) -> u32 {
+        let outstanding = Self::total_outstanding(e);
+        if outstanding <= 0 {
+            return 0;
+        }
+        (Self::rate_weight(e) / outstanding) as u32
+    }
+
+    /// Get the lending pool's assets: deposits plus the interest earned on
+    /// pool loans, less withdrawals and liquidation losses, whether lent out
+    /// or not
+    pub fn total_supplied(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("pool_sup")).unwrap_or(0)
+    }
+
+    /// Get the principal outstanding on loans funded by the lending pool
+    pub fn total_borrowed(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("pool_brw")).unwrap_or(0)
+    }
+
+    /// Get the share of the pool's supply currently lent out, in basis
+    /// points, 0 for an empty pool
+    pub fn utilization_rate(e: &Env) -> u32 {
+        let supplied = Self::total_supplied(e);
+        if supplied <= 0 {
+            return 0;
+        }
+        let borrowed = Self::mul(e, Self::total_borrowed(e), MAX_BPS as i128);
+        (borrowed / supplied).clamp(0, MAX_BPS as i128) as u32
+    }
+
+    /// Get the annual rate, in basis points, new pool loans are created at
+    /// under the rate model and the pool's current utilization
+    pub fn borrow_rate(e: &Env) -> u32 {
+        let model = Self::rate_model(e)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::RateModelNotSet));
+        let spread = (model.max_rate - model.base_rate) as u64;
+        let premium = spread * Self::utilization_rate(e) as u64 / MAX_BPS as u64;
+        model.base_rate + premium as u32
+    }
+
+    /// Get lending stats for the loans backed by tokens of `collection`,
+    /// which is this contract's address for its own tokens
+    pub fn collection_stats(e: &Env, collection: Address) -> CollectionStats {
+        let (active_loans, tvl, collateral_value) = Self::collection_totals(e, &collection);
+        let average_ltv_bps = if collateral_value > 0 {
+            (Self::mul(e, tvl, MAX_BPS as i128) / collateral_value) as u32
+        } else {
+            0
+        };
+        CollectionStats { active_loans, tvl, collateral_value, average_ltv_bps }
+    }
+
+    /// Get how the loans taken out by `borrower` have ended so far
+    pub fn borrower_stats(e: &Env, borrower: Address) -> BorrowerStats {
+        e.storage().persistent().get(&(symbol_short!("b_stats"), borrower)).unwrap_or_default()
+    }
+
+    /// Get protocol-wide lending stats
+    pub fn get_stats(e: &Env) -> ContractStats {
+        ContractStats {
+            total_loans: Self::total_loans(e),
+            active_loans: Self::counter(e, symbol_short!("open_cnt")),
+            total_outstanding: Self::total_outstanding(e),
+            collateral_locked: Self::counter(e, symbol_short!("lock_cnt")),
+            liquidations: Self::counter(e, symbol_short!("liq_cnt")),
+        }
+    }
+
+    /// Get how much more principal can be borrowed across the protocol before
+    /// reaching the maximum TVL. Unbounded while no maximum is set.
+    pub fn available_capacity(e: &Env) -> i128 {
+        match Self::max_tvl(e) {
+            Some(max_tvl) => Self::sub(e, max_tvl, Self::total_outstanding(e)).max(0),
+            None => i128::MAX,
+        }
+    }
+
+    /// Get every operational flag in a single call
+    pub fn operational_status(e: &Env) -> OperationalStatus {
+        let paused = pausable::paused(e);
+        let lending_paused = Self::lending_paused(e);
+        OperationalStatus {
+            paused,
+            lending_paused,
+            borrowing_enabled: !lending_paused,
+            interest_frozen: paused && Self::freeze_pauses_interest(e),
+        }
+    }
+
+    /// Check whether the operational flags currently permit `action`: one of
+    /// `borrow`, `repay`, `liquidate` or `transfer`. Unknown actions are
+    /// reported as not allowed.
+    pub fn action_allowed(e: &Env, action: Symbol) -> bool {
+        if action == symbol_short!("borrow") {
+            !Self::lending_paused(e)
+        } else if action == symbol_short!("transfer") || action == symbol_short!("liquidate") {
+            !pausable::paused(e)
+        } else {
+            action == symbol_short!("repay")
+        }
+    }
+
+    /// Check if NFT is used as collateral
+    pub fn is_collateral(e: &Env, token_id: u32) -> bool {
+        let collection = e.current_contract_address();
+        e.storage().persistent().has(&(symbol_short!("collat"), collection, token_id))
+    }
+
+    /// Get a page of this contract's tokens currently locked as collateral,
+    /// in the order they were locked. At most `limit` ids are returned,
+    /// capped to the maximum page size, starting at `start`.
+    pub fn collateralized_tokens(e: &Env, start: u32, limit: u32) -> Vec<u32> {
+        Self::page(e, &Self::locked_token_ids(e), start, limit)
+    }
+
+    /// Get when a token of `collection` was last liquidated, if ever
+    pub fn last_liquidated_at(e: &Env, collection: Address, token_id: u32) -> Option<u64> {
+        e.storage().persistent().get(&(symbol_short!("liq_at"), collection, token_id))
+    }
+
+    /// Get the id of the open loan an NFT is collateral for, if any
+    pub fn collateral_loan_id(e: &Env, token_id: u32) -> Option<u32> {
+        let collection = e.current_contract_address();
+        e.storage().persistent().get(&(symbol_short!("collat"), collection, token_id))
+    }
+
+    // ===== POOL FUNCTIONS =====
+
+    /// Deposit `amount` of the payment token into the lending pool, returning
+    /// the pool shares minted for it. Shares are a pro-rata claim on the
+    /// pool, which grows with the interest paid on pool loans.
+    pub fn deposit(e: &Env, lender: Address, amount: i128) -> i128 {
+        lender.require_auth();
+        Self::require_positive(e, amount);
+        let supplied = Self::total_supplied(e);
+        let total_shares = Self::total_shares(e);
+        let shares = if total_shares == 0 || supplied <= 0 {
+            amount
+        } else {
+            Self::mul(e, amount, total_shares) / supplied
+        };
+
+        let contract = e.current_contract_address();
+        Self::payment_token_client(e).transfer(&lender, &contract, &amount);
+        Self::adjust_pool_supply(e, amount);
+        Self::set_shares(e, &lender, Self::add(e, Self::shares_of(e, lender.clone()), shares));
+        let total_shares = Self::add(e, total_shares, shares);
+        e.storage().instance().set(&symbol_short!("tot_share"), &total_shares);
+        shares
+    }
+
+    /// Redeem `shares` of the lending pool for their pro-rata part of it,
+    /// returning the amount paid out. Only what isn't lent out can be
+    /// withdrawn.
+    pub fn withdraw(e: &Env, lender: Address, shares: i128) -> i128 {
+        lender.require_auth();
+        Self::require_positive(e, shares);
+        let held = Self::shares_of(e, lender.clone());
+        if shares > held {
+            panic_with_error!(e, LendingNFTError::InsufficientShares);
+        }
+        let total_shares = Self::total_shares(e);
+        let amount = Self::mul(e, shares, Self::total_supplied(e)) / total_shares;
+        if amount > Self::pool_balance(e) {
+            panic_with_error!(e, LendingNFTError::InsufficientLiquidity);
+        }
+
+        Self::set_shares(e, &lender, held - shares);
+        e.storage().instance().set(&symbol_short!("tot_share"), &(total_shares - shares));
+        Self::adjust_pool_supply(e, -amount);
+        let contract = e.current_contract_address();
+        Self::payment_token_client(e).transfer(&contract, &lender, &amount);
+        amount
+    }
+
+    /// Get how much of the lending pool is held by the contract rather than
+    /// lent out, available to borrow or withdraw
+    pub fn pool_balance(e: &Env) -> i128 {
+        Self::total_supplied(e) - Self::total_borrowed(e)
+    }
+
+    /// Get the lending pool shares held by `lender`
+    pub fn shares_of(e: &Env, lender: Address) -> i128 {
+        e.storage().persistent().get(&(symbol_short!("shares"), lender)).unwrap_or(0)
+    }
+
+    /// Get the lending pool shares outstanding
+    pub fn total_shares(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("tot_share")).unwrap_or(0)
+    }
+
+    // ===== ADMIN FUNCTIONS =====
+
+    /// Set the liquidation penalty, in basis points, for loans created from
+    /// now on. The penalty is deducted from any liquidation surplus before it
+    /// is returned to the borrower.
+    pub fn set_liquidation_penalty(e: &Env, caller: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().instance().set(&symbol_short!("liq_pen"), &bps);
+    }
+
+    /// Get the liquidation penalty applied to new loans, in basis points
+    pub fn liquidation_penalty(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("liq_pen")).unwrap_or(0)
+    }
+
+    /// Set the liquidation bonus: the discount, in basis points, off the
+    /// collateral's value at which `liquidate_with_bonus` sells it
+    pub fn set_liquidation_bonus(e: &Env, caller: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().instance().set(&symbol_short!("liq_bonus"), &bps);
+    }
+
+    /// Get the liquidation bonus in basis points
+    pub fn liquidation_bonus(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("liq_bonus")).unwrap_or(0)
+    }
+
+    /// Set the discount, in basis points, taken off the interest of loans
+    /// paid off before half of their duration has elapsed. Zero disables it.
+    pub fn set_early_repay_discount(e: &Env, caller: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().instance().set(&symbol_short!("early_dsc"), &bps);
+    }
+
+    /// Get the early-repayment interest discount in basis points
+    pub fn early_repay_discount(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("early_dsc")).unwrap_or(0)
+    }
+
+    /// Set the annual interest rate, in basis points, of loans created with
+    /// `USE_DEFAULT_RATE`. Loans keep the rate they were created at.
+    pub fn set_default_interest_rate(e: &Env, caller: Address, rate: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("def_rate"), &rate);
+    }
+
+    /// Get the default annual interest rate in basis points, if set
+    pub fn default_interest_rate(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("def_rate"))
+    }
+
+    /// Set the rate model pool loans are priced with
+    pub fn set_rate_model(e: &Env, caller: Address, model: RateModel) {
+        Self::require_owner(e, &caller);
+        if model.base_rate > model.max_rate {
+            panic_with_error!(e, LendingNFTError::InvalidRateModel);
+        }
+        e.storage().instance().set(&symbol_short!("rate_mdl"), &model);
+    }
+
+    /// Get the rate model pool loans are priced with, if set
+    pub fn rate_model(e: &Env) -> Option<RateModel> {
+        e.storage().instance().get(&symbol_short!("rate_mdl"))
+    }
+
+    /// Set a borrower's interest rate discount, in basis points taken off the
+    /// rate of each loan they take from now on. Zero removes it.
+    pub fn set_rate_discount(e: &Env, caller: Address, user: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().persistent().set(&(symbol_short!("rate_disc"), user), &bps);
+    }
+
+    /// Get a borrower's interest rate discount in basis points
+    pub fn rate_discount_bps_of(e: &Env, user: Address) -> u32 {
+        e.storage().persistent().get(&(symbol_short!("rate_disc"), user)).unwrap_or(0)
+    }
+
+    /// Set the annual rate, in basis points, at which loans created from now
+    /// on accrue interest once past their due date
+    pub fn set_late_fee_rate(e: &Env, caller: Address, rate: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("late_fee"), &rate);
+    }
+
+    /// Get the late fee rate applied to new loans, in basis points
+    pub fn late_fee_rate(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("late_fee")).unwrap_or(0)
+    }
+
+    /// Set the origination fee, in basis points of the principal, charged on
+    /// loans created from now on
+    pub fn set_origination_fee(e: &Env, caller: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().instance().set(&symbol_short!("orig_fee"), &bps);
+    }
+
+    /// Get the origination fee in basis points
+    pub fn origination_fee(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("orig_fee")).unwrap_or(0)
+    }
+
+    /// Set the value of a token when used as collateral
+    pub fn set_collateral_value(e: &Env, caller: Address, token_id: u32, value: i128) {
+        Self::require_owner(e, &caller);
+        e.storage().persistent().set(&(symbol_short!("value"), token_id), &value);
+    }
+
+    /// Set the value of a token from an external collection when used as
+    /// collateral
+    pub fn set_external_collateral_value(
+        e: &Env,
+        caller: Address,
+        collection: Address,
+        token_id: u32,
+        value: i128,
+    ) {
+        Self::require_owner(e, &caller);
+        e.storage().persistent().set(&(symbol_short!("value"), collection, token_id), &value);
+    }
+
+    /// Get the value of a token from an external collection when used as
+    /// collateral, 0 if unvalued. Values are rounded down to the valuation
+    /// granularity.
+    pub fn external_collateral_value(e: &Env, collection: Address, token_id: u32) -> i128 {
+        let key = (symbol_short!("value"), collection, token_id);
+        let value = e.storage().persistent().get(&key).unwrap_or(0);
+        Self::round_valuation(e, value)
+    }
+
+    /// Accept NFTs from `collection` as collateral
+    pub fn add_collection(e: &Env, caller: Address, collection: Address) {
+        Self::require_owner(e, &caller);
+        let mut collections = Self::allowed_collections(e);
+        if !collections.contains(&collection) {
+            collections.push_back(collection);
+            e.storage().instance().set(&symbol_short!("colls"), &collections);
+        }
+    }
+
+    /// Stop accepting NFTs from `collection` as collateral for new loans
+    pub fn remove_collection(e: &Env, caller: Address, collection: Address) {
+        Self::require_owner(e, &caller);
+        let mut collections = Self::allowed_collections(e);
+        if let Some(index) = collections.first_index_of(&collection) {
+            collections.remove(index);
+            e.storage().instance().set(&symbol_short!("colls"), &collections);
+        }
+    }
+
+    /// Get the external collections whose NFTs are accepted as collateral
+    pub fn allowed_collections(e: &Env) -> Vec<Address> {
+        e.storage().instance().get(&symbol_short!("colls")).unwrap_or_else(|| Vec::new(e))
+    }
+
+    /// Set the share, in basis points, of a `collection` token's value that
+    /// counts towards the loan-to-value limit, so riskier collections back
+    /// smaller loans
+    pub fn set_collateral_factor(e: &Env, caller: Address, collection: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().persistent().set(&(symbol_short!("coll_fac"), collection), &bps);
+    }
+
+    /// Get the collateral factor of `collection` in basis points, 100% unless
+    /// set
+    pub fn collateral_factor(e: &Env, collection: Address) -> u32 {
+        e.storage().persistent().get(&(symbol_short!("coll_fac"), collection)).unwrap_or(MAX_BPS)
+    }
+
+    /// Get the value of a token when used as collateral. Tokens are priced
+    /// by the oracle when one is configured, falling back as configured by
+    /// `set_price_fallback` when it has no usable price, otherwise by the
+    /// value set by the owner, 0 if unvalued. Values are rounded down to the
+    /// valuation granularity.
+    pub fn collateral_value(e: &Env, token_id: u32) -> i128 {
+        let value = match Self::oracle(e) {
+            Some(oracle) => Self::oracle_price(e, &oracle, token_id),
+            None => Self::static_value(e, token_id),
+        };
+        Self::round_valuation(e, value)
+    }
+
+    /// Choose how collateral is valued when the oracle reports a zero or
+    /// missing price
+    pub fn set_price_fallback(e: &Env, caller: Address, fallback: PriceFallback) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("px_fallbk"), &fallback);
+    }
+
+    /// Get how collateral is valued when the oracle has no usable price
+    pub fn price_fallback(e: &Env) -> PriceFallback {
+        e.storage().instance().get(&symbol_short!("px_fallbk")).unwrap_or(PriceFallback::Revert)
+    }
+
+    /// Set the step collateral valuations are rounded down to before any
+    /// loan-to-value math. Steps of 1 or less disable rounding.
+    pub fn set_valuation_granularity(e: &Env, caller: Address, step: i128) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("val_step"), &step);
+    }
+
+    /// Get the step collateral valuations are rounded down to
+    pub fn valuation_granularity(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("val_step")).unwrap_or(1)
+    }
+
+    /// Set the maximum loan-to-value ratio, in basis points, allowed on new
+    /// loans
+    pub fn set_max_ltv(e: &Env, caller: Address, bps: u32) {
+        Self::require_owner(e, &caller);
+        if bps > MAX_BPS {
+            panic_with_error!(e, LendingNFTError::InvalidBps);
+        }
+        e.storage().instance().set(&symbol_short!("max_ltv"), &bps);
+        e.events().publish((symbol_short!("admin"), symbol_short!("max_ltv")), bps);
+    }
+
+    /// Get the maximum loan-to-value ratio in basis points
+    pub fn max_ltv(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("max_ltv")).unwrap_or(0)
+    }
+
+    /// Get the most principal a single NFT may back, whatever its value.
+    /// Deployments from before the cap have none.
+    pub fn max_loan_per_nft(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("nft_cap")).unwrap_or(i128::MAX)
+    }
+
+    /// Get the smallest principal a loan may be created with. Deployments
+    /// from before the minimum have none.
+    pub fn min_loan_amount(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("min_loan")).unwrap_or(0)
+    }
+
+    /// Get the largest principal that can currently be borrowed against a
+    /// token under the maximum loan-to-value ratio
+    pub fn max_borrowable(e: &Env, token_id: u32) -> i128 {
+        Self::borrowable(e, Self::collateral_value(e, token_id))
+    }
+
+    /// Get the most that can be borrowed against a token from an external
+    /// collection, after its collateral factor
+    pub fn max_borrowable_from_collection(e: &Env, collection: Address, token_id: u32) -> i128 {
+        let value = Self::external_collateral_value(e, collection.clone(), token_id);
+        Self::borrowable(e, Self::weighted_value(e, &collection, value))
+    }
+
+    /// Set the contract paying out the yield earned by a token, for tokens
+    /// that represent yield-bearing positions
+    pub fn set_yield_source(e: &Env, caller: Address, token_id: u32, source: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().persistent().set(&(symbol_short!("yield_src"), token_id), &source);
+    }
+
+    /// Get the yield source of a token, if any
+    pub fn yield_source(e: &Env, token_id: u32) -> Option<Address> {
+        e.storage().persistent().get(&(symbol_short!("yield_src"), token_id))
+    }
+
+    /// Set the maximum TVL: the most principal that may be outstanding on
+    /// active loans at any time
+    pub fn set_max_tvl(e: &Env, caller: Address, max_tvl: i128) {
+        Self::require_owner(e, &caller);
+        if max_tvl < 0 {
+            panic_with_error!(e, LendingNFTError::InvalidAmount);
+        }
+        e.storage().instance().set(&symbol_short!("max_tvl"), &max_tvl);
+    }
+
+    /// Get the maximum TVL, if any
+    pub fn max_tvl(e: &Env) -> Option<i128> {
+        e.storage().instance().get(&symbol_short!("max_tvl"))
+    }
+
+    /// Set how long, in seconds, a payment may be late before the lender can
+    /// mark the loan defaulted, or an overdue loan can be liquidated
+    pub fn set_grace_period(e: &Env, caller: Address, seconds: u64) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("grace"), &seconds);
+    }
+
+    /// Get the grace period for late payments, in seconds
+    pub fn grace_period(e: &Env) -> u64 {
+        e.storage().instance().get(&symbol_short!("grace")).unwrap_or(0)
+    }
+
+    /// Set the most loans the protocol will ever create, counting closed
+    /// ones
+    pub fn set_max_total_loans(e: &Env, caller: Address, max_total_loans: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("max_loans"), &max_total_loans);
+    }
+
+    /// Get the most loans the protocol will ever create, if capped
+    pub fn max_total_loans(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("max_loans"))
+    }
+
+    /// Set the most open loans a single borrower may hold at once
+    pub fn set_max_loans_per_user(e: &Env, caller: Address, max_loans: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("user_max"), &max_loans);
+    }
+
+    /// Get the most open loans a single borrower may hold, if capped
+    pub fn max_loans_per_user(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("user_max"))
+    }
+
+    /// Set the smallest partial repayment accepted, so tiny payments can't
+    /// bloat a loan's payment history. Zero disables it.
+    pub fn set_min_repayment(e: &Env, caller: Address, amount: i128) {
+        Self::require_owner(e, &caller);
+        if amount < 0 {
+            panic_with_error!(e, LendingNFTError::InvalidAmount);
+        }
+        e.storage().instance().set(&symbol_short!("min_repay"), &amount);
+    }
+
+    /// Get the smallest partial repayment accepted
+    pub fn min_repayment(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("min_repay")).unwrap_or(0)
+    }
+
+    /// Set the longest duration, in days, a loan may be created or
+    /// refinanced for
+    pub fn set_max_duration_days(e: &Env, caller: Address, max_days: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("max_days"), &max_days);
+    }
+
+    /// Get the longest duration, in days, a loan may run for, if capped
+    pub fn max_duration_days(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("max_days"))
+    }
+
+    /// Set how many days a liquidated token must wait before it can back a
+    /// new loan
+    pub fn set_recollateralize_cooldown_days(e: &Env, caller: Address, days: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("recol_cd"), &days);
+    }
+
+    /// Get how many days a liquidated token must wait before backing a new
+    /// loan, if any
+    pub fn recollateralize_cooldown_days(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("recol_cd"))
+    }
+
+    /// Set how long, in seconds, the collateral of a fully repaid loan stays
+    /// escrowed before it can be returned
+    pub fn set_collateral_release_delay(e: &Env, caller: Address, seconds: u64) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("rel_delay"), &seconds);
+    }
+
+    /// Get how long the collateral of a repaid loan stays escrowed, in seconds
+    pub fn collateral_release_delay(e: &Env) -> u64 {
+        e.storage().instance().get(&symbol_short!("rel_delay")).unwrap_or(0)
+    }
+
+    /// Register `keeper` as allowed to liquidate loans on their lenders'
+    /// behalf
+    pub fn add_keeper(e: &Env, caller: Address, keeper: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().persistent().set(&(symbol_short!("keeper"), keeper), &true);
+    }
+
+    /// Let `operator` create and repay loans on `borrower`'s behalf
+    pub fn approve_operator(e: &Env, borrower: Address, operator: Address) {
+        borrower.require_auth();
+        e.storage().persistent().set(&(symbol_short!("operator"), borrower, operator), &true);
+    }
+
+    /// Withdraw an operator's approval to act for `borrower`
+    pub fn revoke_operator(e: &Env, borrower: Address, operator: Address) {
+        borrower.require_auth();
+        e.storage().persistent().remove(&(symbol_short!("operator"), borrower, operator));
+    }
+
+    /// Check whether `operator` may act for `borrower`
+    pub fn is_approved_operator(e: &Env, borrower: Address, operator: Address) -> bool {
+        e.storage().persistent().has(&(symbol_short!("operator"), borrower, operator))
+    }
+
+    /// Remove `keeper` from the registered keepers
+    pub fn remove_keeper(e: &Env, caller: Address, keeper: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().persistent().remove(&(symbol_short!("keeper"), keeper));
+    }
+
+    /// Check whether `addr` is a registered keeper
+    pub fn is_keeper(e: &Env, addr: Address) -> bool {
+        e.storage().persistent().has(&(symbol_short!("keeper"), addr))
+    }
+
+    /// Set how many ledgers `bump_loan_ttl` keeps a loan live for, capped to
+    /// the network's maximum
+    pub fn set_loan_ttl(e: &Env, caller: Address, ledgers: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("loan_ttl"), &ledgers);
+    }
+
+    /// Get how many ledgers `bump_loan_ttl` keeps a loan live for
+    pub fn loan_ttl(e: &Env) -> u32 {
+        e.storage().instance().get(&symbol_short!("loan_ttl")).unwrap_or(DEFAULT_LOAN_TTL)
+    }
+
+    /// Set the smallest liquidation surplus worth returning to a borrower.
+    /// Smaller remainders are swept to the protocol. Zero disables sweeping.
+    pub fn set_dust_threshold(e: &Env, caller: Address, threshold: i128) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("dust"), &threshold);
+    }
+
+    /// Get the dust threshold for liquidation surpluses
+    pub fn dust_threshold(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("dust")).unwrap_or(0)
+    }
+
+    /// Set the token loans are denominated in. It is given at construction;
+    /// deployments from before then set it here after upgrading.
+    pub fn set_payment_token(e: &Env, caller: Address, token: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("pay_token"), &token);
+    }
+
+    /// Get the token loans are denominated in, which bids and payouts are
+    /// made in
+    pub fn payment_token(e: &Env) -> Address {
+        e.storage().instance().get(&symbol_short!("pay_token"))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::PaymentTokenNotSet))
+    }
+
+    /// Set the oracle used to value collateral
+    pub fn set_oracle(e: &Env, caller: Address, oracle: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("oracle"), &oracle);
+    }
+
+    /// Get the oracle used to value collateral, if configured
+    pub fn oracle(e: &Env) -> Option<Address> {
+        e.storage().instance().get(&symbol_short!("oracle"))
+    }
+
+    /// Enable or disable price-based liquidation. Loans are only liquidated
+    /// on time while it is disabled. While enabled, new loans require an
+    /// oracle to be configured.
+    pub fn set_price_liquidation(e: &Env, caller: Address, enabled: bool) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("price_liq"), &enabled);
+    }
+
+    /// Check whether price-based liquidation is enabled
+    pub fn price_liquidation_enabled(e: &Env) -> bool {
+        e.storage().instance().get(&symbol_short!("price_liq")).unwrap_or(false)
+    }
+
+    /// Choose whether repayments to closed loans revert (the default) or are
+    /// refunded and otherwise ignored
+    pub fn set_refund_closed_repayments(e: &Env, caller: Address, enabled: bool) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("refund_cl"), &enabled);
+    }
+
+    /// Check whether repayments to closed loans are refunded instead of
+    /// reverting
+    pub fn refunds_closed_repayments(e: &Env) -> bool {
+        e.storage().instance().get(&symbol_short!("refund_cl")).unwrap_or(false)
+    }
+
+    /// Allow anyone to compound the interest of open loans through `accrue`
+    pub fn set_compounding_enabled(e: &Env, caller: Address, enabled: bool) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("compound"), &enabled);
+    }
+
+    /// Check whether loan interest may be compounded
+    pub fn compounding_enabled(e: &Env) -> bool {
+        e.storage().instance().get(&symbol_short!("compound")).unwrap_or(false)
+    }
+
+    /// Choose whether interest stops accruing while the contract is paused.
+    /// Applies to every loan, including time frozen before the change.
+    pub fn set_freeze_pauses_interest(e: &Env, caller: Address, enabled: bool) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("frz_int"), &enabled);
+    }
+
+    /// Check whether interest stops accruing while the contract is paused
+    pub fn freeze_pauses_interest(e: &Env) -> bool {
+        e.storage().instance().get(&symbol_short!("frz_int")).unwrap_or(false)
+    }
+
+    /// Pause the origination of new loans. Repayments, liquidations and NFT
+    /// transfers carry on as usual.
+    pub fn pause_lending(e: &Env, caller: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("lend_paus"), &true);
+    }
+
+    /// Resume the origination of new loans
+    pub fn unpause_lending(e: &Env, caller: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("lend_paus"), &false);
+    }
+
+    /// Check whether the origination of new loans is paused
+    pub fn lending_paused(e: &Env) -> bool {
+        e.storage().instance().get(&symbol_short!("lend_paus")).unwrap_or(false)
+    }
+
+    /// Pause lending automatically once more than `threshold` loans are
+    /// liquidated through `liquidate_loan` within an hour
+    pub fn set_circuit_breaker_threshold(e: &Env, caller: Address, threshold: u32) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().set(&symbol_short!("cb_limit"), &threshold);
+    }
+
+    /// Get the circuit breaker's liquidation threshold, if one is set
+    pub fn circuit_breaker_threshold(e: &Env) -> Option<u32> {
+        e.storage().instance().get(&symbol_short!("cb_limit"))
+    }
+
+    /// Clear the liquidations counted by the circuit breaker and resume the
+    /// origination of new loans
+    pub fn reset_circuit_breaker(e: &Env, caller: Address) {
+        Self::require_owner(e, &caller);
+        e.storage().instance().remove(&symbol_short!("liq_times"));
+        e.storage().instance().set(&symbol_short!("lend_paus"), &false);
+    }
+
+    /// Move one of this contract's tokens held by the contract to `to`. Meant
+    /// for recovering stuck assets, so only allowed while paused. Loan
+    /// records are left untouched, so a token backing an open loan can't be
+    /// moved until the loan is force-closed.
+    #[when_paused]
+    pub fn emergency_withdraw_nft(e: &Env, caller: Address, token_id: u32, to: Address) {
+        Self::require_owner(e, &caller);
+        if Self::is_collateral(e, token_id) {
+            panic_with_error!(e, LendingNFTError::CollateralLocked);
+        }
+        Self::move_native_token(e, &e.current_contract_address(), &to, token_id);
+        let topics = (Symbol::new(e, "emergency"), symbol_short!("nft"));
+        e.events().publish(topics, (token_id, to));
+    }
+
+    /// Move `amount` of `token` held by the contract to `to`. Meant for
+    /// recovering stuck assets, so only allowed while paused. Of the payment
+    /// token, only what the contract holds beyond the pool's unborrowed
+    /// supply, escrowed bids and booked protocol fees can be withdrawn.
+    #[when_paused]
+    pub fn emergency_withdraw_token(
+        e: &Env,
+        caller: Address,
+        token: Address,
+        amount: i128,
+        to: Address,
+    ) {
+        Self::require_owner(e, &caller);
+        Self::require_positive(e, amount);
+        if token == Self::payment_token(e) {
+            let held = TokenClient::new(e, &token).balance(&e.current_contract_address());
+            let reserved = Self::add(e, Self::pool_balance(e), Self::escrowed_bids(e));
+            let reserved = Self::add(e, reserved, Self::protocol_fees(e));
+            if amount > held - reserved {
+                panic_with_error!(e, LendingNFTError::FundsReserved);
+            }
+        }
+        TokenClient::new(e, &token).transfer(&e.current_contract_address(), &to, &amount);
+        let topics = (Symbol::new(e, "emergency"), symbol_short!("token"));
+        e.events().publish(topics, (token, amount, to));
+    }
+
+    /// Settle a loan administratively, whatever it owes, in a dispute or
+    /// after an exploit. Closing it as `Repaid` returns the collateral to the
+    /// borrower and as `Liquidated` hands it to the lender, which a pool
+    /// loan can't be closed as. A running auction is cancelled and its
+    /// highest bid refunded. Only allowed while paused.
+    #[when_paused]
+    pub fn force_close_loan(
+        e: &Env,
+        loan_id: u32,
+        final_status: LoanStatus,
+        reason: Symbol,
+        caller: Address,
+    ) {
+        Self::require_owner(e, &caller);
+        let mut loan = Self::load_loan(e, loan_id);
+        if !Self::is_open(&loan) && loan.status != LoanStatus::Liquidating {
+            panic_with_error!(e, LendingNFTError::LoanNotActive);
+        }
+        if final_status == LoanStatus::Liquidated && Self::is_pool_loan(e, &loan) {
+            panic_with_error!(e, LendingNFTError::PoolCannotHoldCollateral);
+        }
+        if let Some(auction) = Self::auction(e, loan_id) {
+            if let Some(bidder) = &auction.bidder {
+                let contract = e.current_contract_address();
+                Self::payment_token_client(e).transfer(&contract, bidder, &auction.bid);
+            }
+            e.storage().persistent().remove(&(symbol_short!("auction"), loan_id));
+        }
+
+        match final_status {
+            LoanStatus::Repaid => {
+                let principal = Self::principal_due(&loan);
+                if Self::is_pool_loan(e, &loan) {
+                    Self::adjust_pool_supply(e, -principal);
+                }
+                Self::adjust_outstanding(e, &loan, -principal);
+                Self::move_status(e, loan_id, &mut loan, LoanStatus::Repaid);
+                Self::save_loan(e, loan_id, &loan);
+                Self::unlock_collateral(e, &loan);
+                let key = (symbol_short!("repaid_at"), loan_id);
+                e.storage().persistent().set(&key, &e.ledger().timestamp());
+                Self::transfer_collateral(e, &loan, &loan.borrower);
+            }
+            LoanStatus::Liquidated => {
+                let lender = loan.lender.clone();
+                Self::close_liquidation(e, loan_id, &mut loan, 0, lender.clone(), false);
+                Self::transfer_collateral(e, &loan, &lender);
+            }
+            _ => panic_with_error!(e, LendingNFTError::InvalidFinalStatus),
+        }
+        let topics = (symbol_short!("admin"), Symbol::new(e, "force_closed"));
+        e.events().publish(topics, (loan_id, final_status, reason));
+    }
+
+    /// Get the fees collected by the protocol
+    pub fn protocol_fees(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("fees")).unwrap_or(0)
+    }
+
+    // ===== HELPER FUNCTIONS =====
+
+    /// Require `caller`'s authorization and that it is `borrower` or one of
+    /// their approved operators.
+    fn require_borrower_or_operator(e: &Env, borrower: &Address, caller: &Address) {
+        caller.require_auth();
+        if caller != borrower && !Self::is_approved_operator(e, borrower.clone(), caller.clone()) {
+            panic_with_error!(e, LendingNFTError::NotBorrower);
+        }
+    }
+
+    fn require_owner(e: &Env, caller: &Address) {
+        caller.require_auth();
+        let owner: Address = e.storage().instance().get(&symbol_short!("owner"))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::OwnerNotSet));
+        if *caller != owner {
+            panic_with_error!(e, LendingNFTError::Unauthorized);
+        }
+    }
+
+    /// Escrow one of this contract's tokens and open a loan against it.
+    fn open_native_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+        installments: u32,
+    ) -> u32 {
+        let (mut loan, value) =
+            Self::native_loan(e, borrower, token_id, amount, interest_rate, duration_days);
+        loan.installments = installments;
+        Self::escrow_and_open(e, loan, value)
+    }
+
+    /// Build a new loan against one of this contract's tokens, returning it
+    /// with the collateral's value.
+    fn native_loan(
+        e: &Env,
+        borrower: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+    ) -> (Loan, i128) {
+        // Check if caller owns the NFT
+        if Base::owner_of(e, token_id) != borrower {
+            panic_with_error!(e, LendingNFTError::NotTokenOwner);
+        }
+
+        // Check if NFT is already used as collateral
+        if Self::is_collateral(e, token_id) {
+            panic_with_error!(e, LendingNFTError::AlreadyCollateral);
+        }
+        Self::check_cooldown(e, &e.current_contract_address(), token_id);
+
+        let value = Self::collateral_value(e, token_id);
+        Self::check_loan_limits(e, value, amount);
+        let collection = e.current_contract_address();
+        let loan =
+            Self::new_loan(e, borrower, collection, token_id, amount, interest_rate, duration_days);
+        (loan, value)
+    }
+
+    /// Escrow the collateral of a new loan on one of this contract's tokens
+    /// and open the loan.
+    fn escrow_and_open(e: &Env, loan: Loan, value: i128) -> u32 {
+        // Escrow the NFT until it is reclaimed. Every caller has already
+        // authorized the loan for the borrower, possibly through an operator,
+        // so the move skips the borrower's own authorization.
+        let contract = e.current_contract_address();
+        Self::move_native_token(e, &loan.borrower, &contract, loan.token_id);
+        Self::open_loan(e, loan, value)
+    }
+
+    /// Move one of this contract's tokens without asking `from` to authorize
+    /// it, keeping the owner enumeration in step. Used to escrow collateral
+    /// whose move is already authorized and to release it from escrow, where
+    /// the contract can't authorize a transfer from itself.
+    fn move_native_token(e: &Env, from: &Address, to: &Address, token_id: u32) {
+        Base::update(e, Some(from), Some(to), token_id);
+        Enumerable::remove_from_owner_enumeration(e, from, token_id);
+        Enumerable::add_to_owner_enumeration(e, to, token_id);
+        emit_transfer(e, from, to, token_id);
+    }
+
+    /// Rate a loan requested at `interest_rate` by `borrower` is created at,
+    /// after their discount. `USE_DEFAULT_RATE` stands for the default rate.
+    fn resolved_rate(e: &Env, borrower: &Address, interest_rate: u32) -> u32 {
+        let interest_rate = if interest_rate == USE_DEFAULT_RATE {
+            Self::default_interest_rate(e)
+                .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::DefaultRateNotSet))
+        } else {
+            interest_rate
+        };
+        interest_rate.saturating_sub(Self::rate_discount_bps_of(e, borrower.clone()))
+    }
+
+    /// Build a new active loan on the current terms of the protocol.
+    fn new_loan(
+        e: &Env,
+        borrower: Address,
+        collection: Address,
+        token_id: u32,
+        amount: i128,
+        interest_rate: u32,
+        duration_days: u32,
+    ) -> Loan {
+        // Charge the origination fee up front
+        let lender = e.storage().instance().get(&symbol_short!("owner"))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::OwnerNotSet));
+        let interest_rate = Self::resolved_rate(e, &borrower, interest_rate);
+        Self::check_duration(e, duration_days);
+        Loan {
+            borrower,
+            lender,
+            collection,
+            token_id,
+            collateral_value: 0,
+            amount,
+            interest_rate,
+            duration_days,
+            created_at: e.ledger().timestamp(),
+            installments: 0,
+            interest_only_days: 0,
+            status: LoanStatus::Active,
+            repaid: 0,
+            liquidation_penalty_bps: Self::liquidation_penalty(e),
+            late_fee_rate: Self::late_fee_rate(e),
+            fees: Self::bps_of(e, amount, Self::origination_fee(e)),
+            fees_paid: 0,
+            interest_paid: 0,
+            principal_paid: 0,
+            liquidator: None,
+            last_accrual_timestamp: e.ledger().timestamp(),
+            accrued_interest: 0,
+            kind: LoanKind::FixedTerm,
+            called_at: None,
+            extra_collateral: Vec::new(e),
+        }
+    }
+
+    /// Refuse a token of `collection` liquidated within the
+    /// recollateralization cooldown.
+    fn check_cooldown(e: &Env, collection: &Address, token_id: u32) {
+        let (Some(days), Some(liquidated_at)) = (
+            Self::recollateralize_cooldown_days(e),
+            Self::last_liquidated_at(e, collection.clone(), token_id),
+        ) else {
+            return;
+        };
+        let cooldown = days as u64 * SECONDS_PER_DAY;
+        if e.ledger().timestamp() < liquidated_at.saturating_add(cooldown) {
+            panic_with_error!(e, LendingNFTError::RecollateralizeCooldown);
+        }
+    }
+
+    /// Refuse a partial repayment below the minimum. Paying off the loan is
+    /// always accepted.
+    fn check_repayment(e: &Env, loan: &Loan, amount: i128) {
+        if amount < Self::min_repayment(e) && amount < Self::balance_of(e, loan) {
+            panic_with_error!(e, LendingNFTError::RepaymentTooSmall);
+        }
+    }
+
+    /// Check a loan duration against the protocol's cap.
+    fn check_duration(e: &Env, duration_days: u32) {
+        if Self::max_duration_days(e).is_some_and(|max| duration_days > max) {
+            panic_with_error!(e, LendingNFTError::DurationTooLong);
+        }
+    }
+
+    /// Check a new loan of `amount` against collateral worth `value`.
+    fn check_loan_limits(e: &Env, value: i128, amount: i128) {
+        if Self::lending_paused(e) {
+            panic_with_error!(e, LendingNFTError::LendingPaused);
+        }
+        Self::require_positive(e, amount);
+        if amount < Self::min_loan_amount(e) {
+            panic_with_error!(e, LendingNFTError::LoanTooSmall);
+        }
+        Self::check_exposure(e, value, amount);
+        if Self::max_total_loans(e).is_some_and(|max| Self::get_next_loan_id(e) > max) {
+            panic_with_error!(e, LendingNFTError::LoanCapReached);
+        }
+
+        // Price-based liquidation can't be enforced without an oracle
+        if Self::price_liquidation_enabled(e) && Self::oracle(e).is_none() {
+            panic_with_error!(e, LendingNFTError::OracleNotConfigured);
+        }
+    }
+
+    /// Check `amount` of principal against the loan-to-value limit for
+    /// collateral worth `value`, the per-NFT maximum and the TVL left.
+    fn check_exposure(e: &Env, value: i128, amount: i128) {
+        // Check the principal against the collateral's loan-to-value limit
+        let borrowable = Self::borrowable(e, value);
+        if borrowable <= 0 || amount > borrowable {
+            panic_with_error!(e, LendingNFTError::LtvExceeded);
+        }
+        if amount > Self::max_loan_per_nft(e) {
+            panic_with_error!(e, LendingNFTError::LoanPerNftExceeded);
+        }
+        if amount > Self::available_capacity(e) {
+            panic_with_error!(e, LendingNFTError::TvlExceeded);
+        }
+    }
+
+    /// Store a new loan backed by collateral worth `value`, lock the
+    /// collateral and pay out the principal, returning the loan id.
+    fn open_loan(e: &Env, mut loan: Loan, value: i128) -> u32 {
+        if Self::max_loans_per_user(e)
+            .is_some_and(|max| Self::open_loan_count(e, &loan.borrower) >= max)
+        {
+            panic_with_error!(e, LendingNFTError::TooManyActiveLoans);
+        }
+
+        let loan_id = Self::get_next_loan_id(e);
+        loan.collateral_value = value;
+        Self::record_loan(e, loan_id, &loan);
+        Self::lock_collateral(e, loan_id, &loan);
+        Self::adjust_outstanding(e, &loan, loan.amount);
+        Self::disburse(e, &loan);
+        Self::publish_loan_event(e, symbol_short!("created"), loan_id, &loan);
+
+        Self::increment_next_loan_id(e);
+        loan_id
+    }
 
-#[contract]
-pub struct LendingNFT;
+    /// Pay a new loan's principal to its borrower, out of the pool for a pool
+    /// loan or from the lender, who must authorize it, otherwise.
+    fn disburse(e: &Env, loan: &Loan) {
+        let from = if Self::is_pool_loan(e, loan) {
+            e.current_contract_address()
+        } else {
+            loan.lender.require_auth();
+            loan.lender.clone()
+        };
+        Self::payment_token_client(e).transfer(&from, &loan.borrower, &loan.amount);
+    }
 
-#[contractimpl]
-impl LendingNFT {
-    pub fn __constructor(e: &Env, owner: Address) {
-        let uri = String::from_str(e, "www.lendingnft.com");
-        let name = String::from_str(e, "LendingNFT");
-        let symbol = String::from_str(e, "LNF");
-        Base::set_metadata(e, uri, name, symbol);
-        e.storage().instance().set(&symbol_short!("owner"), &owner);
+    /// Number of `borrower`'s loans that are still open.
+    fn open_loan_count(e: &Env, borrower: &Address) -> u32 {
+        let mut count = 0;
+        for loan_id in Self::get_user_loans(e, borrower.clone()).iter() {
+            if Self::is_open(&Self::load_loan(e, loan_id)) {
+                count = Self::increment(e, count);
+            }
+        }
+        count
     }
 
-    #[when_not_paused]
-    pub fn mint(e: &Env, to: Address, token_id: u32, caller: Address) {
-        let owner = e.storage().instance().get(&symbol_short!("owner"))
-            .unwrap_or_else(|| panic_with_error!(e, Error::from_contract_error(1)));
-        if caller != owner {
-            panic_with_error!(e, Error::from_contract_error(2));
+    /// Publish a `("loan", action, loan_id, nonce)` event carrying the loan.
+    fn publish_loan_event(e: &Env, action: Symbol, loan_id: u32, loan: &Loan) {
+        Self::publish_event(e, symbol_short!("loan"), action, loan_id, loan.clone());
+    }
+
+    /// Publish a `(scope, action, loan_id, nonce)` event, bumping the loan's
+    /// action nonce.
+    fn publish_event<D>(e: &Env, scope: Symbol, action: Symbol, loan_id: u32, data: D)
+    where
+        D: IntoVal<Env, Val>,
+    {
+        let key = (symbol_short!("nonce"), loan_id);
+        let nonce = Self::action_nonce(e, loan_id).checked_add(1)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        e.storage().persistent().set(&key, &nonce);
+        e.events().publish((scope, action, loan_id, nonce), data);
+    }
+
+    /// Store a loan along with its original terms and index it by borrower
+    /// and status.
+    fn record_loan(e: &Env, loan_id: u32, loan: &Loan) {
+        let terms = LoanTerms {
+            borrower: loan.borrower.clone(),
+            token_id: loan.token_id,
+            amount: loan.amount,
+            interest_rate: loan.interest_rate,
+            duration_days: loan.duration_days,
+            created_at: loan.created_at,
+            fees: loan.fees,
+        };
+        e.storage().persistent().set(&(symbol_short!("terms"), loan_id), &terms);
+        Self::add_to_user_loans(e, &loan.borrower, loan_id);
+        Self::save_loan(e, loan_id, loan);
+        Self::add_to_status_list(e, loan.status, loan_id);
+    }
+
+    /// When a loan was first created, from its original terms.
+    fn created_at(e: &Env, loan_id: u32) -> Option<u64> {
+        let key = (symbol_short!("terms"), loan_id);
+        e.storage().persistent().get::<_, LoanTerms>(&key).map(|terms| terms.created_at)
+    }
+
+    /// Current value of a loan's collateral.
+    fn value_of(e: &Env, loan: &Loan) -> i128 {
+        let mut value = Self::token_value(e, &loan.collection, loan.token_id);
+        for token_id in loan.extra_collateral.iter() {
+            value = Self::add(e, value, Self::token_value(e, &loan.collection, token_id));
         }
-        Enumerable::non_sequential_mint(e, &to, token_id);
+        value
     }
 
-    // ===== LENDING FUNCTIONS =====
+    /// `value` of `collection` collateral after the collection's collateral
+    /// factor, as it counts towards the loan-to-value limit.
+    fn weighted_value(e: &Env, collection: &Address, value: i128) -> i128 {
+        Self::bps_of(e, value, Self::collateral_factor(e, collection.clone()))
+    }
 
-    /// Create a loan using NFT as collateral
-    pub fn create_loan(
-        e: &Env,
-        borrower: Address,
-        token_id: u32,
-        amount: i128,
-        interest_rate: u32,
-        duration_days: u32,
-        _caller: Address
-    ) -> u32 {
-        // Check if caller owns the NFT
-        if Enumerable::owner_of(e, token_id) != borrower {
-            panic_with_error!(e, Error::from_contract_error(3));
+    /// Current value of a single token of `collection`.
+    fn token_value(e: &Env, collection: &Address, token_id: u32) -> i128 {
+        if *collection == e.current_contract_address() {
+            Self::collateral_value(e, token_id)
+        } else {
+            Self::external_collateral_value(e, collection.clone(), token_id)
         }
+    }
 
-        // Check if NFT is already used as collateral
+    fn round_valuation(e: &Env, value: i128) -> i128 {
+        let step = Self::valuation_granularity(e);
+        if step <= 1 {
+            return value;
+        }
+        value - value.rem_euclid(step)
+    }
+
+    /// Largest principal that collateral worth `value` can back.
+    fn borrowable(e: &Env, value: i128) -> i128 {
+        Self::bps_of(e, value.max(0), Self::max_ltv(e))
+    }
+
+    fn require_positive(e: &Env, amount: i128) {
+        if amount <= 0 {
+            panic_with_error!(e, LendingNFTError::InvalidAmount);
+        }
+    }
+
+    /// Refuse to move or burn a token that backs an open loan.
+    fn require_unlocked(e: &Env, token_id: u32) {
         if Self::is_collateral(e, token_id) {
-            panic_with_error!(e, Error::from_contract_error(4));
+            panic_with_error!(e, LendingNFTError::CollateralLocked);
         }
+    }
 
-        let loan_id = Self::get_next_loan_id(e);
-        
-        // Store loan data
-        e.storage().instance().set(&symbol_short!("borrower"), &borrower);
-        e.storage().instance().set(&symbol_short!("amount"), &amount);
-        e.storage().instance().set(&symbol_short!("rate"), &interest_rate);
-        e.storage().instance().set(&symbol_short!("duration"), &duration_days);
-        e.storage().instance().set(&symbol_short!("created"), &e.ledger().timestamp());
-        e.storage().instance().set(&symbol_short!("status"), &0u32); // Active
-        e.storage().instance().set(&symbol_short!("repaid"), &0i128);
-        
-        // Mark token as collateral
-        e.storage().instance().set(&symbol_short!("collat"), &token_id);
-        
-        Self::increment_next_loan_id(e);
-        loan_id
+    /// Query `oracle` for the price of `token_id`, applying the price
+    /// fallback when it reports a zero or missing price.
+    fn oracle_price(e: &Env, oracle: &Address, token_id: u32) -> i128 {
+        let key = (symbol_short!("last_px"), token_id);
+        match OracleClient::new(e, oracle).try_price(&token_id) {
+            Ok(Ok(price)) if price > 0 => {
+                e.storage().persistent().set(&key, &price);
+                return price;
+            }
+            _ => {}
+        }
+        let fallback = match Self::price_fallback(e) {
+            PriceFallback::Revert => None,
+            PriceFallback::LastKnown => e.storage().persistent().get(&key),
+            PriceFallback::Static => Some(Self::static_value(e, token_id)),
+        };
+        fallback.unwrap_or_else(|| panic_with_error!(e, LendingNFTError::OracleUnavailable))
     }
 
-    /// Repay a loan
-    pub fn repay_loan(
+    /// Get the value set by the owner for a token, 0 if unvalued.
+    fn static_value(e: &Env, token_id: u32) -> i128 {
+        e.storage().persistent().get(&(symbol_short!("value"), token_id)).unwrap_or(0)
+    }
+
+    fn load_loan(e: &Env, loan_id: u32) -> Loan {
+        e.storage().persistent().get(&(symbol_short!("loan"), loan_id))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::LoanNotFound))
+    }
+
+    fn save_loan(e: &Env, loan_id: u32, loan: &Loan) {
+        e.storage().persistent().set(&(symbol_short!("loan"), loan_id), loan);
+    }
+
+    /// Load the auction of a loan currently being liquidated. Closed loans
+    /// can't be liquidated again.
+    fn load_auction(e: &Env, loan_id: u32) -> Auction {
+        match Self::load_loan(e, loan_id).status {
+            LoanStatus::Liquidating => {}
+            LoanStatus::Active | LoanStatus::Defaulted => {
+                panic_with_error!(e, LendingNFTError::NotLiquidating)
+            }
+            LoanStatus::Liquidated => panic_with_error!(e, LendingNFTError::AlreadyLiquidated),
+            LoanStatus::Repaid => panic_with_error!(e, LendingNFTError::LoanNotActive),
+        }
+        e.storage().persistent().get(&(symbol_short!("auction"), loan_id))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::NotLiquidating))
+    }
+
+    fn payment_token_client(e: &Env) -> TokenClient<'_> {
+        TokenClient::new(e, &Self::payment_token(e))
+    }
+
+    /// Transfer a loan's escrowed collateral out of the contract to `to`.
+    fn transfer_collateral(e: &Env, loan: &Loan, to: &Address) {
+        // Loans migrated from version 0 were never escrowed
+        if loan.collection == e.current_contract_address()
+            && Base::owner_of(e, loan.token_id) == *to
+        {
+            return;
+        }
+        Self::transfer_token(e, &loan.collection, loan.token_id, to);
+        for token_id in loan.extra_collateral.iter() {
+            Self::transfer_token(e, &loan.collection, token_id, to);
+        }
+    }
+
+    /// Transfer an escrowed token of `collection` out of the contract to `to`.
+    fn transfer_token(e: &Env, collection: &Address, token_id: u32, to: &Address) {
+        let contract = e.current_contract_address();
+        if *collection == contract {
+            Self::move_native_token(e, &contract, to, token_id);
+        } else {
+            CollectionClient::new(e, collection).transfer(&contract, to, &token_id);
+        }
+    }
+
+    fn collateral_holder(e: &Env, loan: &Loan) -> Address {
+        if loan.collection == e.current_contract_address() {
+            Base::owner_of(e, loan.token_id)
+        } else {
+            CollectionClient::new(e, &loan.collection).owner_of(&loan.token_id)
+        }
+    }
+
+    /// Mark a loan's collateral as locked and count it in its collection's
+    /// stats.
+    fn lock_collateral(e: &Env, loan_id: u32, loan: &Loan) {
+        Self::lock_token(e, loan_id, &loan.collection, loan.token_id);
+        let (active_loans, tvl, value) = Self::collection_totals(e, &loan.collection);
+        let totals = (active_loans + 1, tvl, Self::add(e, value, loan.collateral_value));
+        Self::save_collection_totals(e, &loan.collection, totals);
+    }
+
+    /// Mark a single token as collateral for `loan_id`.
+    fn lock_token(e: &Env, loan_id: u32, collection: &Address, token_id: u32) {
+        let key = (symbol_short!("collat"), collection.clone(), token_id);
+        e.storage().persistent().set(&key, &loan_id);
+        Self::adjust_counter(e, symbol_short!("lock_cnt"), true);
+        if *collection == e.current_contract_address() {
+            let mut ids = Self::locked_token_ids(e);
+            ids.push_back(token_id);
+            e.storage().persistent().set(&symbol_short!("coll_ids"), &ids);
+        }
+    }
+
+    /// Send the collateral of a repaid loan back to its borrower, once its
+    /// release delay has passed.
+    fn release_collateral(e: &Env, loan_id: u32, loan: Loan) {
+        if loan.status != LoanStatus::Repaid {
+            panic_with_error!(e, LendingNFTError::NotRepaid);
+        }
+        if Self::collateral_holder(e, &loan) != e.current_contract_address() {
+            panic_with_error!(e, LendingNFTError::AlreadyReclaimed);
+        }
+        let repaid_at: u64 =
+            e.storage().persistent().get(&(symbol_short!("repaid_at"), loan_id)).unwrap_or(0);
+        let released_at = repaid_at.saturating_add(Self::collateral_release_delay(e));
+        if e.ledger().timestamp() < released_at {
+            panic_with_error!(e, LendingNFTError::CollateralNotReleased);
+        }
+
+        Self::transfer_collateral(e, &loan, &loan.borrower);
+        let scope = Symbol::new(e, "collateral");
+        Self::publish_event(e, scope, symbol_short!("reclaimed"), loan_id, loan);
+    }
+
+    /// Ids of this contract's tokens currently locked as collateral.
+    fn locked_token_ids(e: &Env) -> Vec<u32> {
+        e.storage().persistent().get(&symbol_short!("coll_ids")).unwrap_or_else(|| Vec::new(e))
+    }
+
+    /// Undo `lock_collateral` once a loan is closed.
+    fn unlock_collateral(e: &Env, loan: &Loan) {
+        Self::unlock_token(e, &loan.collection, loan.token_id);
+        for token_id in loan.extra_collateral.iter() {
+            Self::unlock_token(e, &loan.collection, token_id);
+        }
+        let (active_loans, tvl, value) = Self::collection_totals(e, &loan.collection);
+        let active_loans = active_loans.checked_sub(1)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        let totals = (active_loans, tvl, Self::sub(e, value, loan.collateral_value));
+        Self::save_collection_totals(e, &loan.collection, totals);
+    }
+
+    /// Undo `lock_token`.
+    fn unlock_token(e: &Env, collection: &Address, token_id: u32) {
+        let key = (symbol_short!("collat"), collection.clone(), token_id);
+        e.storage().persistent().remove(&key);
+        Self::adjust_counter(e, symbol_short!("lock_cnt"), false);
+        if *collection == e.current_contract_address() {
+            let mut ids = Self::locked_token_ids(e);
+            if let Some(index) = ids.first_index_of(token_id) {
+                ids.remove(index);
+                e.storage().persistent().set(&symbol_short!("coll_ids"), &ids);
+            }
+        }
+    }
+
+    /// Close a liquidated loan after `recovered` of its debt was paid to the
+    /// lender and the collateral went to `liquidator`, releasing the
+    /// collateral record. Liquidations forced by the market, rather than
+    /// surrendered or settled by the owner, count towards the circuit
+    /// breaker.
+    fn close_liquidation(
         e: &Env,
-        _loan_id: u32,
-        amount: i128,
-        caller: Address
+        loan_id: u32,
+        loan: &mut Loan,
+        recovered: i128,
+        liquidator: Address,
+        market: bool,
     ) {
-        let borrower: Address = e.storage().instance().get(&symbol_short!("borrower"))
-            .unwrap_or_else(|| panic_with_error!(e, Error::from_contract_error(5)));
-        
-        if borrower != caller {
-            panic_with_error!(e, Error::from_contract_error(6));
+        loan.repaid = Self::add(e, loan.repaid, recovered);
+        loan.liquidator = Some(liquidator);
+        let principal = Self::principal_due(loan);
+        // The pool gains what was recovered beyond the principal, or loses
+        // what was not recovered of it
+        if Self::is_pool_loan(e, loan) {
+            Self::adjust_pool_supply(e, recovered - principal);
+        }
+        Self::adjust_outstanding(e, loan, -principal);
+        Self::move_status(e, loan_id, loan, LoanStatus::Liquidated);
+        Self::save_loan(e, loan_id, loan);
+        Self::unlock_collateral(e, loan);
+        let now = e.ledger().timestamp();
+        let mut tokens = loan.extra_collateral.clone();
+        tokens.push_front(loan.token_id);
+        for token_id in tokens.iter() {
+            let key = (symbol_short!("liq_at"), loan.collection.clone(), token_id);
+            e.storage().persistent().set(&key, &now);
+        }
+        Self::adjust_counter(e, symbol_short!("liq_cnt"), true);
+        Self::record_outcome(e, &loan.borrower, LoanStatus::Liquidated);
+        Self::publish_loan_event(e, Symbol::new(e, "liquidated"), loan_id, loan);
+        if market {
+            Self::check_circuit_breaker(e);
+        }
+    }
+
+    /// Count a loan reaching `status` in its borrower's stats.
+    fn record_outcome(e: &Env, borrower: &Address, status: LoanStatus) {
+        let mut stats = Self::borrower_stats(e, borrower.clone());
+        match status {
+            LoanStatus::Repaid => stats.repaid = Self::increment(e, stats.repaid),
+            LoanStatus::Defaulted => stats.defaulted = Self::increment(e, stats.defaulted),
+            LoanStatus::Liquidated => stats.liquidated = Self::increment(e, stats.liquidated),
+            _ => return,
+        }
+        e.storage().persistent().set(&(symbol_short!("b_stats"), borrower.clone()), &stats);
+    }
+
+    fn status_list(e: &Env, status: LoanStatus) -> Vec<u32> {
+        e.storage().persistent().get(&(symbol_short!("by_status"), status))
+            .unwrap_or_else(|| Vec::new(e))
+    }
+
+    fn add_to_status_list(e: &Env, status: LoanStatus, loan_id: u32) {
+        let mut ids = Self::status_list(e, status);
+        ids.push_back(loan_id);
+        e.storage().persistent().set(&(symbol_short!("by_status"), status), &ids);
+        if matches!(status, LoanStatus::Active | LoanStatus::Defaulted) {
+            Self::adjust_counter(e, symbol_short!("open_cnt"), true);
+        }
+    }
+
+    fn add_to_user_loans(e: &Env, user: &Address, loan_id: u32) {
+        let mut ids = Self::get_user_loans(e, user.clone());
+        ids.push_back(loan_id);
+        e.storage().persistent().set(&(symbol_short!("user_loan"), user.clone()), &ids);
+    }
+
+    /// Transition a loan to `status`, keeping the per-status lists in sync.
+    fn move_status(e: &Env, loan_id: u32, loan: &mut Loan, status: LoanStatus) {
+        Self::remove_from_status_list(e, loan.status, loan_id);
+        Self::add_to_status_list(e, status, loan_id);
+        loan.status = status;
+    }
+
+    fn remove_from_status_list(e: &Env, status: LoanStatus, loan_id: u32) {
+        let mut ids = Self::status_list(e, status);
+        if let Some(index) = ids.first_index_of(loan_id) {
+            ids.remove(index);
+            e.storage().persistent().set(&(symbol_short!("by_status"), status), &ids);
+            if matches!(status, LoanStatus::Active | LoanStatus::Defaulted) {
+                Self::adjust_counter(e, symbol_short!("open_cnt"), false);
+            }
+        }
+    }
+
+    fn counter(e: &Env, key: Symbol) -> u32 {
+        e.storage().instance().get(&key).unwrap_or(0)
+    }
+
+    /// Count one more, or one fewer, under `key`.
+    fn adjust_counter(e: &Env, key: Symbol, up: bool) {
+        let count = Self::counter(e, key.clone());
+        let count = if up { Self::increment(e, count) } else { count.saturating_sub(1) };
+        e.storage().instance().set(&key, &count);
+    }
+
+    fn page(e: &Env, ids: &Vec<u32>, start: u32, limit: u32) -> Vec<u32> {
+        let end = start.saturating_add(limit.min(MAX_PAGE_SIZE)).min(ids.len());
+        if start >= end {
+            return Vec::new(e);
+        }
+        ids.slice(start..end)
+    }
+
+    fn detail_of(e: &Env, loan_id: u32, loan: Loan) -> LoanDetail {
+        LoanDetail {
+            loan_id,
+            accrued_interest: Self::accrued_interest(e, &loan),
+            outstanding_balance: Self::balance_of(e, &loan),
+            due_at: Self::due_at(&loan),
+            total_fees_charged: loan.fees,
+            liquidator: loan.liquidator.clone(),
+            loan,
+        }
+    }
+
+    fn balance_of(e: &Env, loan: &Loan) -> i128 {
+        if !Self::is_open(loan) {
+            return 0;
+        }
+        let due = Self::add(e, Self::fees_due(loan), Self::interest_due(e, loan));
+        Self::add(e, due, Self::principal_due(loan))
+    }
+
+    /// Length of the loan in seconds. Computed in u64, where even the
+    /// longest duration fits. An open-term loan runs until the end of the
+    /// repayment window after it is called, and indefinitely until then.
+    fn term(loan: &Loan) -> u64 {
+        let duration = loan.duration_days as u64 * SECONDS_PER_DAY;
+        match (loan.kind, loan.called_at) {
+            (LoanKind::FixedTerm, _) => duration,
+            (LoanKind::OpenTerm, Some(called_at)) => {
+                called_at.saturating_sub(loan.created_at).saturating_add(duration)
+            }
+            (LoanKind::OpenTerm, None) => u64::MAX,
+        }
+    }
+
+    /// Whether `loan` is an open-term loan its lender has not called yet.
+    fn awaiting_call(loan: &Loan) -> bool {
+        loan.kind == LoanKind::OpenTerm && loan.called_at.is_none()
+    }
+
+    fn due_at(loan: &Loan) -> u64 {
+        loan.created_at.saturating_add(Self::term(loan))
+    }
+
+    /// An active loan becomes liquidatable once it is past its due date and
+    /// grace period, a defaulted one straight away.
+    fn is_liquidatable(e: &Env, loan: &Loan) -> bool {
+        match loan.status {
+            LoanStatus::Active => {
+                e.ledger().timestamp() > Self::due_at(loan).saturating_add(Self::grace_period(e))
+            }
+            LoanStatus::Defaulted => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the loan is still owed: active or defaulted.
+    fn is_open(loan: &Loan) -> bool {
+        matches!(loan.status, LoanStatus::Active | LoanStatus::Defaulted)
+    }
+
+    /// Total the loan owes over its full term if repaid on schedule.
+    fn scheduled_total(e: &Env, loan: &Loan) -> i128 {
+        let term = Self::term(loan);
+        let interest = Self::simple_interest(e, loan.amount, loan.interest_rate, term);
+        Self::add(e, Self::add(e, loan.fees, loan.amount), interest)
+    }
+
+    /// Due date and cumulative amount due of installment `n` (1-based). A
+    /// loan without a schedule has a single installment at its due date.
+    fn installment(e: &Env, loan: &Loan, n: u32) -> (u64, i128) {
+        let count = loan.installments.max(1);
+        let elapsed = Self::term(loan) as u128 * n as u128 / count as u128;
+        let due = loan.created_at.saturating_add(elapsed as u64);
+        (due, Self::mul(e, Self::scheduled_total(e, loan), n as i128) / count as i128)
+    }
+
+    /// Due date and amount still owed of the earliest installment not yet
+    /// covered by payments. Once every installment is covered, what remains
+    /// (such as late interest) is due at once.
+    fn next_installment(e: &Env, loan: &Loan) -> (u64, i128) {
+        if Self::awaiting_call(loan) {
+            return (Self::due_at(loan), Self::balance_of(e, loan));
+        }
+        let paid = Self::add(e, loan.fees_paid, loan.interest_paid);
+        let paid = Self::add(e, paid, loan.principal_paid);
+        let count = loan.installments.max(1) as i128;
+        let total = Self::scheduled_total(e, loan);
+        // First installment whose cumulative amount due exceeds what was paid
+        let owed = Self::mul(e, Self::add(e, paid, 1), count);
+        let n = Self::add(e, owed, total - 1) / total;
+        if n > count {
+            return (Self::due_at(loan), Self::balance_of(e, loan));
+        }
+        let (due, cumulative) = Self::installment(e, loan, n as u32);
+        (due, Self::sub(e, cumulative, paid))
+    }
+
+    fn accrued_interest(e: &Env, loan: &Loan) -> i128 {
+        Self::accrued_interest_at(e, loan, e.ledger().timestamp())
+    }
+
+    /// Interest the loan will have accrued by `at`.
+    fn accrued_interest_at(e: &Env, loan: &Loan, at: u64) -> i128 {
+        let term = Self::term(loan);
+        let now = Self::accruing_time_at(e, loan, at);
+        let then = Self::accruing_time_at(e, loan, loan.last_accrual_timestamp);
+        let in_term = now.min(term).saturating_sub(then.min(term));
+        let late = now.saturating_sub(term).saturating_sub(then.saturating_sub(term));
+
+        // Interest compounded so far earns interest like the principal
+        let unpaid = (loan.accrued_interest - loan.interest_paid).max(0);
+        let base = Self::add(e, loan.amount, unpaid);
+        let since = Self::add(
+            e,
+            Self::simple_interest(e, base, loan.interest_rate, in_term),
+            Self::simple_interest(e, base, loan.late_fee_rate, late),
+        );
+        Self::add(e, loan.accrued_interest, since)
+    }
+
+    fn fees_due(loan: &Loan) -> i128 {
+        loan.fees - loan.fees_paid
+    }
+
+    fn interest_due(e: &Env, loan: &Loan) -> i128 {
+        Self::interest_due_at(e, loan, e.ledger().timestamp())
+    }
+
+    fn interest_due_at(e: &Env, loan: &Loan, at: u64) -> i128 {
+        let accrued = Self::accrued_interest_at(e, loan, at);
+        let accrued = Self::apply_early_repay_discount(e, loan, accrued, at);
+        (accrued - loan.interest_paid).max(0)
+    }
+
+    /// Whether the loan is still within its interest-only window.
+    fn in_interest_only_period(e: &Env, loan: &Loan) -> bool {
+        let window = loan.interest_only_days as u64 * SECONDS_PER_DAY;
+        e.ledger().timestamp() < loan.created_at.saturating_add(window)
+    }
+
+    fn principal_due(loan: &Loan) -> i128 {
+        loan.amount - loan.principal_paid
+    }
+
+    /// Seconds of accrual elapsed between the loan's creation and `at`,
+    /// capped at its duration.
+    fn elapsed_in_term(e: &Env, loan: &Loan, at: u64) -> u64 {
+        Self::accruing_time_at(e, loan, at).min(Self::term(loan))
+    }
+
+    /// Seconds of accrual elapsed past the loan's due date.
+    fn elapsed_past_due(e: &Env, loan: &Loan) -> u64 {
+        Self::accruing_time(e, loan).saturating_sub(Self::term(loan))
+    }
+
+    /// Seconds elapsed since the loan was created, leaving out time the
+    /// contract spent frozen when freezes pause interest.
+    fn accruing_time(e: &Env, loan: &Loan) -> u64 {
+        Self::accruing_time_at(e, loan, e.ledger().timestamp())
+    }
+
+    /// Seconds of accrual between the loan's creation and `at`.
+    fn accruing_time_at(e: &Env, loan: &Loan, at: u64) -> u64 {
+        let elapsed = at.saturating_sub(loan.created_at);
+        if !Self::freeze_pauses_interest(e) {
+            return elapsed;
+        }
+        elapsed.saturating_sub(Self::frozen_time(e, loan.created_at, at))
+    }
+
+    /// Seconds between `from` and `to` during which the contract was frozen.
+    fn frozen_time(e: &Env, from: u64, to: u64) -> u64 {
+        let mut windows = Self::freeze_windows(e);
+        if let Some(start) = e.storage().instance().get(&symbol_short!("frz_start")) {
+            windows.push_back((start, to));
         }
+        windows.iter()
+            .map(|(start, end)| end.min(to).saturating_sub(start.max(from)))
+            .sum()
+    }
+
+    /// Completed freezes, as `(start, end)` timestamps.
+    fn freeze_windows(e: &Env) -> Vec<(u64, u64)> {
+        e.storage().instance().get(&symbol_short!("freezes")).unwrap_or_else(|| Vec::new(e))
+    }
 
-        let status = e.storage().instance().get(&symbol_short!("status")).unwrap_or(1u32);
-        if status != 0 {
-            panic_with_error!(e, Error::from_contract_error(7));
+    /// Interest on `principal` over `seconds` at `annual_rate` basis points,
+    /// rounded down.
+    fn simple_interest(e: &Env, principal: i128, annual_rate: u32, seconds: u64) -> i128 {
+        Self::mul(e, Self::mul(e, principal, annual_rate as i128), seconds as i128)
+            / (MAX_BPS as i128 * (DAYS_PER_YEAR * SECONDS_PER_DAY) as i128)
+    }
+
+    /// Reduce `interest` by the early-repayment discount while, at `at`, less
+    /// than half of the loan's duration has elapsed.
+    fn apply_early_repay_discount(e: &Env, loan: &Loan, interest: i128, at: u64) -> i128 {
+        let discount_bps = Self::early_repay_discount(e);
+        let term = Self::term(loan);
+        // An open-term loan has no term to be early in until it is called
+        if discount_bps == 0 || Self::awaiting_call(loan) {
+            return interest;
         }
+        if Self::elapsed_in_term(e, loan, at) * 2 >= term {
+            return interest;
+        }
+        interest - Self::bps_of(e, interest, discount_bps)
+    }
 
-        // Simple repayment - just update repaid amount
-        let repaid = e.storage().instance().get(&symbol_short!("repaid")).unwrap_or(0i128);
-        let new_repaid = repaid + amount;
-        e.storage().instance().set(&symbol_short!("repaid"), &new_repaid);
+    /// Allocate a payment through the waterfall (fees first, then accrued
+    /// interest, then principal) and close the loan once nothing is owed.
+    fn apply_payment(e: &Env, loan_id: u32, loan: &mut Loan, amount: i128) -> Repayment {
+        let fees_due = Self::fees_due(loan);
+        let interest_due = Self::interest_due(e, loan);
+        let principal_due = Self::principal_due(loan);
+        let to_fees = amount.min(fees_due).max(0);
+        let to_interest = (amount - to_fees).min(interest_due).max(0);
+        let to_principal = (amount - to_fees - to_interest).min(principal_due).max(0);
+
+        loan.fees_paid = Self::add(e, loan.fees_paid, to_fees);
+        loan.interest_paid = Self::add(e, loan.interest_paid, to_interest);
+        loan.principal_paid = Self::add(e, loan.principal_paid, to_principal);
+        // Anything beyond what is owed is not accepted
+        let applied = to_fees + to_interest + to_principal;
+        loan.repaid = Self::add(e, loan.repaid, applied);
+        Self::record_payment(e, loan_id, applied);
+        Self::adjust_outstanding(e, loan, -to_principal);
+        Self::book_protocol_fees(e, to_fees);
+        if Self::is_pool_loan(e, loan) {
+            Self::adjust_pool_supply(e, to_interest);
+        } else if to_interest > 0 {
+            let key = (symbol_short!("int_earn"), loan.lender.clone());
+            let earned = Self::lender_interest_earned(e, loan.lender.clone());
+            e.storage().persistent().set(&key, &Self::add(e, earned, to_interest));
+        }
 
         // If fully repaid, mark as repaid
-        let loan_amount = e.storage().instance().get(&symbol_short!("amount")).unwrap_or(0i128);
-        if new_repaid >= loan_amount {
-            e.storage().instance().set(&symbol_short!("status"), &1u32);
-            e.storage().instance().set(&symbol_short!("collat"), &0u32);
+        let total_due = Self::add(e, Self::add(e, fees_due, interest_due), principal_due);
+        let repaid = amount >= total_due;
+        if repaid {
+            Self::move_status(e, loan_id, loan, LoanStatus::Repaid);
+            Self::unlock_collateral(e, loan);
+            let key = (symbol_short!("repaid_at"), loan_id);
+            e.storage().persistent().set(&key, &e.ledger().timestamp());
+            Self::record_outcome(e, &loan.borrower, LoanStatus::Repaid);
+        }
+        Self::save_loan(e, loan_id, loan);
+
+        let repayment = Repayment { fees: to_fees, interest: to_interest, principal: to_principal };
+        let action = symbol_short!("repayment");
+        Self::publish_event(e, symbol_short!("loan"), action, loan_id, repayment.clone());
+        if repaid {
+            Self::publish_loan_event(e, symbol_short!("repaid"), loan_id, loan);
         }
+        repayment
     }
 
-    /// Get loan information
-    pub fn get_loan_info(e: &Env, _loan_id: u32) -> (Address, i128, u32, u32, u64, u32, i128) {
-        let borrower: Address = e.storage().instance().get(&symbol_short!("borrower"))
-            .unwrap_or_else(|| panic_with_error!(e, Error::from_contract_error(8)));
-        let amount = e.storage().instance().get(&symbol_short!("amount")).unwrap_or(0i128);
-        let interest_rate = e.storage().instance().get(&symbol_short!("rate")).unwrap_or(0u32);
-        let duration = e.storage().instance().get(&symbol_short!("duration")).unwrap_or(0u32);
-        let created_at = e.storage().instance().get(&symbol_short!("created")).unwrap_or(0u64);
-        let status = e.storage().instance().get(&symbol_short!("status")).unwrap_or(1u32);
-        let repaid = e.storage().instance().get(&symbol_short!("repaid")).unwrap_or(0i128);
-        
-        (borrower, amount, interest_rate, duration, created_at, status, repaid)
+    /// Append a payment to a loan's history, dropping the oldest once it
+    /// holds `MAX_PAYMENT_HISTORY` entries.
+    fn record_payment(e: &Env, loan_id: u32, amount: i128) {
+        let mut payments = Self::get_payments(e, loan_id);
+        if payments.len() >= MAX_PAYMENT_HISTORY {
+            payments.pop_front();
+        }
+        payments.push_back((e.ledger().timestamp(), amount));
+        e.storage().persistent().set(&(symbol_short!("payments"), loan_id), &payments);
     }
 
-    /// Check if NFT is used as collateral
-    pub fn is_collateral(e: &Env, token_id: u32) -> bool {
-        e.storage().instance().get(&symbol_short!("collat")).unwrap_or(0u32) == token_id
+    /// Split a liquidation surplus between the borrower and the protocol.
+    /// The loan's penalty is charged to the loan, booked as protocol fees,
+    /// and the remainder, returned here, is owed to the borrower. Remainders
+    /// below the dust threshold are swept to the protocol instead.
+    pub(crate) fn settle_surplus(e: &Env, loan: &mut Loan, surplus: i128) -> i128 {
+        if surplus <= 0 {
+            return 0;
+        }
+        let penalty = Self::bps_of(e, surplus, loan.liquidation_penalty_bps);
+        loan.fees = Self::add(e, loan.fees, penalty);
+        loan.fees_paid = Self::add(e, loan.fees_paid, penalty);
+        let to_borrower = surplus - penalty;
+        if to_borrower < Self::dust_threshold(e) {
+            Self::book_protocol_fees(e, surplus);
+            return 0;
+        }
+        Self::book_protocol_fees(e, penalty);
+        to_borrower
     }
 
-    // ===== HELPER FUNCTIONS =====
+    /// Migrate storage from version `from` to `from + 1`.
+    fn migrate_from(e: &Env, from: u32) {
+        if from == 0 {
+            Self::migrate_legacy_loan(e);
+        }
+    }
+
+    /// Move the single loan kept in instance storage before versioning into
+    /// the per-loan records. Its collateral was never escrowed and stays with
+    /// the borrower, locked against transfers until the loan is closed.
+    fn migrate_legacy_loan(e: &Env) {
+        let storage = e.storage().instance();
+        let Some(borrower) = storage.get::<_, Address>(&symbol_short!("borrower")) else {
+            return;
+        };
+        let lender = storage.get(&symbol_short!("owner"))
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::OwnerNotSet));
+        let amount: i128 = storage.get(&symbol_short!("amount")).unwrap_or(0);
+        let repaid: i128 = storage.get(&symbol_short!("repaid")).unwrap_or(0);
+        let active = storage.get(&symbol_short!("status")).unwrap_or(1u32) == 0 && repaid < amount;
+        let loan_id = Self::get_next_loan_id(e) - 1;
+        let created_at = storage.get(&symbol_short!("created")).unwrap_or(0);
+        let loan = Loan {
+            borrower,
+            lender,
+            collection: e.current_contract_address(),
+            token_id: storage.get(&symbol_short!("collat")).unwrap_or(0),
+            collateral_value: 0,
+            amount,
+            interest_rate: storage.get(&symbol_short!("rate")).unwrap_or(0),
+            duration_days: storage.get(&symbol_short!("duration")).unwrap_or(0),
+            created_at,
+            installments: 0,
+            interest_only_days: 0,
+            status: if active { LoanStatus::Active } else { LoanStatus::Repaid },
+            repaid,
+            liquidation_penalty_bps: 0,
+            late_fee_rate: 0,
+            fees: 0,
+            fees_paid: 0,
+            interest_paid: 0,
+            principal_paid: repaid.min(amount),
+            liquidator: None,
+            last_accrual_timestamp: created_at,
+            accrued_interest: 0,
+            kind: LoanKind::FixedTerm,
+            called_at: None,
+            extra_collateral: Vec::new(e),
+        };
+
+        Self::record_loan(e, loan_id, &loan);
+        if active {
+            Self::lock_collateral(e, loan_id, &loan);
+            Self::adjust_outstanding(e, &loan, Self::principal_due(&loan));
+        }
+        for key in [
+            symbol_short!("borrower"),
+            symbol_short!("amount"),
+            symbol_short!("rate"),
+            symbol_short!("duration"),
+            symbol_short!("created"),
+            symbol_short!("status"),
+            symbol_short!("repaid"),
+            symbol_short!("collat"),
+        ] {
+            storage.remove(&key);
+        }
+    }
+
+    /// Whether a loan was funded by the lending pool.
+    fn is_pool_loan(e: &Env, loan: &Loan) -> bool {
+        loan.lender == e.current_contract_address()
+    }
+
+    fn adjust_pool_supply(e: &Env, delta: i128) {
+        let supplied = Self::add(e, Self::total_supplied(e), delta);
+        e.storage().instance().set(&symbol_short!("pool_sup"), &supplied);
+    }
+
+    fn set_shares(e: &Env, lender: &Address, shares: i128) {
+        e.storage().persistent().set(&(symbol_short!("shares"), lender.clone()), &shares);
+    }
+
+    /// Pull what `repayment` applied to a loan from `payer`. Fees are kept by
+    /// the contract, where they are booked as protocol fees; interest and
+    /// principal go into the pool for a pool loan or to the lender otherwise.
+    fn collect_repayment(e: &Env, loan: &Loan, payer: &Address, repayment: &Repayment) {
+        let token = Self::payment_token_client(e);
+        let contract = e.current_contract_address();
+        if repayment.fees > 0 {
+            token.transfer(payer, &contract, &repayment.fees);
+        }
+        let to_lender = repayment.interest + repayment.principal;
+        if to_lender > 0 {
+            let to = if Self::is_pool_loan(e, loan) { contract } else { loan.lender.clone() };
+            token.transfer(payer, &to, &to_lender);
+        }
+    }
+
+    /// Pay `amount` held by the contract to a loan's lender. What is owed to
+    /// the pool stays in the contract.
+    fn pay_lender(e: &Env, loan: &Loan, amount: i128) {
+        if !Self::is_pool_loan(e, loan) {
+            let contract = e.current_contract_address();
+            Self::payment_token_client(e).transfer(&contract, &loan.lender, &amount);
+        }
+    }
+
+    /// Sell a loan's collateral to `buyer` at its current value less the
+    /// liquidation bonus, paying the debt to the lender and the rest, net of
+    /// the liquidation penalty, to the borrower, and close the loan.
+    fn sell_collateral(e: &Env, loan_id: u32, loan: &mut Loan, buyer: Address) {
+        let value = Self::value_of(e, loan).max(0);
+        let price = value - Self::bps_of(e, value, Self::liquidation_bonus(e));
+        let recovered = price.min(Self::balance_of(e, loan));
+
+        let token = Self::payment_token_client(e);
+        let contract = e.current_contract_address();
+        token.transfer(&buyer, &contract, &price);
+        Self::pay_lender(e, loan, recovered);
+        let refund = Self::settle_surplus(e, loan, price - recovered);
+        if refund > 0 {
+            token.transfer(&contract, &loan.borrower, &refund);
+        }
+
+        Self::close_liquidation(e, loan_id, loan, recovered, buyer.clone(), true);
+        Self::transfer_collateral(e, loan, &buyer);
+    }
+
+    /// Count a liquidation towards the circuit breaker, pausing lending if
+    /// the trailing window now holds more than the threshold allows.
+    fn check_circuit_breaker(e: &Env) {
+        let Some(threshold) = Self::circuit_breaker_threshold(e) else {
+            return;
+        };
+        let now = e.ledger().timestamp();
+        let key = symbol_short!("liq_times");
+        let mut times: Vec<u64> = e.storage().instance().get(&key).unwrap_or(Vec::new(e));
+        while times.first().is_some_and(|t| t.saturating_add(CIRCUIT_BREAKER_WINDOW) <= now) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        e.storage().instance().set(&key, &times);
+
+        if times.len() > threshold && !Self::lending_paused(e) {
+            e.storage().instance().set(&symbol_short!("lend_paus"), &true);
+            e.events().publish((symbol_short!("circuit"), symbol_short!("tripped")), times.len());
+        }
+    }
+
+    fn book_protocol_fees(e: &Env, amount: i128) {
+        let fees = Self::add(e, Self::protocol_fees(e), amount);
+        e.storage().instance().set(&symbol_short!("fees"), &fees);
+    }
+
+    /// Payment tokens escrowed as the highest bid of a running auction.
+    fn escrowed_bids(e: &Env) -> i128 {
+        let mut total = 0;
+        for loan_id in Self::status_list(e, LoanStatus::Liquidating).iter() {
+            if let Some(auction) = Self::auction(e, loan_id) {
+                total = Self::add(e, total, auction.bid);
+            }
+        }
+        total
+    }
+
+    fn add(e: &Env, a: i128, b: i128) -> i128 {
+        a.checked_add(b)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow))
+    }
+
+    fn sub(e: &Env, a: i128, b: i128) -> i128 {
+        a.checked_sub(b)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow))
+    }
+
+    /// `n + 1`, refusing to wrap.
+    fn increment(e: &Env, n: u32) -> u32 {
+        n.checked_add(1)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow))
+    }
+
+    fn mul(e: &Env, a: i128, b: i128) -> i128 {
+        a.checked_mul(b)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow))
+    }
+
+    /// `bps` basis points of `amount`.
+    fn bps_of(e: &Env, amount: i128, bps: u32) -> i128 {
+        Self::mul(e, amount, bps as i128) / MAX_BPS as i128
+    }
+
+    /// Adjust the principal outstanding, both protocol-wide and for the
+    /// loan's collection, and the pool's borrowed total for a loan it
+    /// funded, by `delta`.
+    fn adjust_outstanding(e: &Env, loan: &Loan, delta: i128) {
+        let total = Self::add(e, Self::total_outstanding(e), delta);
+        e.storage().instance().set(&symbol_short!("tvl"), &total);
+        if Self::is_pool_loan(e, loan) {
+            let borrowed = Self::add(e, Self::total_borrowed(e), delta);
+            e.storage().instance().set(&symbol_short!("pool_brw"), &borrowed);
+        }
+        let weighted = Self::mul(e, delta, loan.interest_rate as i128);
+        let rate_weight = Self::add(e, Self::rate_weight(e), weighted);
+        e.storage().instance().set(&symbol_short!("rate_wt"), &rate_weight);
+        let (active_loans, tvl, value) = Self::collection_totals(e, &loan.collection);
+        let totals = (active_loans, Self::add(e, tvl, delta), value);
+        Self::save_collection_totals(e, &loan.collection, totals);
+    }
+
+    /// Sum over open loans of principal outstanding times interest rate.
+    fn rate_weight(e: &Env) -> i128 {
+        e.storage().instance().get(&symbol_short!("rate_wt")).unwrap_or(0)
+    }
+
+    /// Open loans, principal outstanding and collateral value at origination
+    /// of a collection.
+    fn collection_totals(e: &Env, collection: &Address) -> (u32, i128, i128) {
+        e.storage().persistent().get(&(symbol_short!("coll_stat"), collection.clone()))
+            .unwrap_or((0, 0, 0))
+    }
+
+    fn save_collection_totals(e: &Env, collection: &Address, totals: (u32, i128, i128)) {
+        e.storage().persistent().set(&(symbol_short!("coll_stat"), collection.clone()), &totals);
+    }
 
     fn get_next_loan_id(e: &Env) -> u32 {
         e.storage().instance().get(&symbol_short!("next_id")).unwrap_or(1)
@@ -136,7 +3515,9 @@ impl LendingNFT {
 
     fn increment_next_loan_id(e: &Env) {
         let current = Self::get_next_loan_id(e);
-        e.storage().instance().set(&symbol_short!("next_id"), &(current + 1));
+        let next = current.checked_add(1)
+            .unwrap_or_else(|| panic_with_error!(e, LendingNFTError::ArithmeticOverflow));
+        e.storage().instance().set(&symbol_short!("next_id"), &next);
     }
 }
 
@@ -149,11 +3530,13 @@ impl NonFungibleToken for LendingNFT {
 
     #[when_not_paused]
     fn transfer(e: &Env, from: Address, to: Address, token_id: u32) {
+        Self::require_unlocked(e, token_id);
         Self::ContractType::transfer(e, &from, &to, token_id);
     }
 
     #[when_not_paused]
     fn transfer_from(e: &Env, spender: Address, from: Address, to: Address, token_id: u32) {
+        Self::require_unlocked(e, token_id);
         Self::ContractType::transfer_from(e, &spender, &from, &to, token_id);
     }
 }
@@ -162,11 +3545,13 @@ impl NonFungibleToken for LendingNFT {
 impl NonFungibleBurnable for LendingNFT {
     #[when_not_paused]
     fn burn(e: &Env, from: Address, token_id: u32) {
+        Self::require_unlocked(e, token_id);
         Self::ContractType::burn(e, &from, token_id);
     }
 
     #[when_not_paused]
     fn burn_from(e: &Env, spender: Address, from: Address, token_id: u32) {
+        Self::require_unlocked(e, token_id);
         Self::ContractType::burn_from(e, &spender, &from, token_id);
     }
 }
@@ -182,20 +3567,19 @@ impl Pausable for LendingNFT {
     }
 
     fn pause(e: &Env, caller: Address) {
-        let owner = e.storage().instance().get(&symbol_short!("owner"))
-            .unwrap_or_else(|| panic_with_error!(e, Error::from_contract_error(1)));
-        if caller != owner {
-            panic_with_error!(e, Error::from_contract_error(2));
-        }
+        Self::require_owner(e, &caller);
         pausable::pause(e);
+        e.storage().instance().set(&symbol_short!("frz_start"), &e.ledger().timestamp());
     }
 
     fn unpause(e: &Env, caller: Address) {
-        let owner = e.storage().instance().get(&symbol_short!("owner"))
-            .unwrap_or_else(|| panic_with_error!(e, Error::from_contract_error(1)));
-        if caller != owner {
-            panic_with_error!(e, Error::from_contract_error(2));
-        }
+        Self::require_owner(e, &caller);
         pausable::unpause(e);
+        if let Some(start) = e.storage().instance().get::<_, u64>(&symbol_short!("frz_start")) {
+            let mut windows = Self::freeze_windows(e);
+            windows.push_back((start, e.ledger().timestamp()));
+            e.storage().instance().set(&symbol_short!("freezes"), &windows);
+            e.storage().instance().remove(&symbol_short!("frz_start"));
+        }
     }
 }
\ No newline at end of file
@@ -1,329 +1,3815 @@
 // SPDX-License-Identifier: MIT
 #![cfg(test)]
 
-use super::*;
-use soroban_sdk::{testutils::Address as _, Address, Env, String, symbol_short};
-
-fn create_contract() -> (Env, LendingContract, Address, Address) {
-    let env = Env::default();
-    let contract = LendingContract;
-    let owner = Address::generate(&env);
-    let borrower = Address::generate(&env);
-    
-    contract.__constructor(&env, owner.clone());
-    
-    (env, contract, owner, borrower)
-}
-
-#[test]
-fn test_constructor() {
-    let (env, contract, owner, _) = create_contract();
-    
-    // Verificar se o owner foi definido corretamente
-    assert!(contract.owner(&env) == owner);
-}
-
-#[test]
-fn test_mint_nft() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Verificar se o NFT foi criado
-    assert_eq!(contract.owner_of(&env, token_id), Some(borrower));
-}
-
-#[test]
-fn test_create_loan() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let loan_amount = 1000i128;
-    let interest_rate = 500u32; // 5%
-    let duration_days = 30u32;
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        loan_amount,
-        interest_rate,
-        duration_days,
-        borrower.clone()
-    );
-    
-    // Verificar se o empréstimo foi criado
+extern crate std;
+
+use soroban_sdk::{
+    contract, contractimpl, symbol_short,
+    testutils::{
+        storage::Persistent as _, Address as _, Events, Ledger, MockAuth, MockAuthInvoke,
+    },
+    token::{StellarAssetClient, TokenClient},
+    vec, xdr, Address, Env, IntoVal, Symbol, Val, Vec,
+};
+
+use crate::contract::{
+    BorrowerStats, CollectionStats, ContractStats, LendingNFT, LendingNFTClient, LoanGuards,
+    LoanKind, LoanStatus, LoanSummary, LoanTerms, OperationalStatus, PriceFallback, RateModel,
+    Repayment, USE_DEFAULT_RATE,
+};
+
+/// Payment tokens the owner, who lends on native loans, starts with.
+const LENDER_FUNDS: i128 = 1_000_000_000_000;
+
+fn create_client<'a>(e: &Env, owner: &Address) -> LendingNFTClient<'a> {
+    e.mock_all_auths();
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    StellarAssetClient::new(e, &token).mint(owner, &LENDER_FUNDS);
+    let address = e.register(LendingNFT, (owner, 5000u32, i128::MAX, 0i128, 0u32, token));
+    LendingNFTClient::new(e, &address)
+}
+
+#[test]
+fn constructor_stores_payment_token() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    let address = e.register(LendingNFT, (&owner, 5000u32, i128::MAX, 0i128, 0u32, &token));
+    let client = LendingNFTClient::new(&e, &address);
+
+    assert_eq!(client.payment_token(), token);
+}
+
+/// Mint a token valued high enough to back any loan used in these tests.
+fn mint(client: &LendingNFTClient, owner: &Address, to: &Address, token_id: u32) {
+    client.mint(to, &token_id, owner);
+    client.set_collateral_value(owner, &token_id, &1_000_000_000);
+}
+
+fn fund(e: &Env, client: &LendingNFTClient, to: &Address, amount: i128) {
+    StellarAssetClient::new(e, &client.payment_token()).mint(to, &amount);
+}
+
+fn mint_and_borrow(
+    client: &LendingNFTClient,
+    owner: &Address,
+    borrower: &Address,
+    token_id: u32,
+    amount: i128,
+) -> u32 {
+    mint(client, owner, borrower, token_id);
+    client.create_loan(borrower, &token_id, &amount, &500, &30, borrower)
+}
+
+#[test]
+fn mint_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.mint(&borrower, &1, &owner);
+    assert_eq!(client.owner_of(&1), borrower);
+    assert_eq!(client.balance(&borrower), 1);
+}
+
+#[test]
+fn create_loan_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
     assert_eq!(loan_id, 1);
-    
-    // Verificar se o token é usado como colateral
-    assert!(contract.is_collateral(&env, token_id));
-    
-    // Verificar informações do empréstimo
-    let loan_info = contract.get_loan_info(&env, loan_id);
-    assert_eq!(loan_info.borrower, borrower);
-    assert_eq!(loan_info.collateral_token_id, token_id);
-    assert_eq!(loan_info.loan_amount, loan_amount);
-    assert_eq!(loan_info.interest_rate, interest_rate);
-    assert_eq!(loan_info.duration_days, duration_days);
-    assert_eq!(loan_info.status, LoanStatus::Active);
-    assert_eq!(loan_info.repaid_amount, 0);
-}
-
-#[test]
-fn test_repay_loan() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let loan_amount = 1000i128;
-    let interest_rate = 500u32; // 5%
-    let duration_days = 30u32;
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        loan_amount,
-        interest_rate,
-        duration_days,
-        borrower.clone()
-    );
-    
-    // Pagar parte do empréstimo
-    let partial_payment = 500i128;
-    contract.repay_loan(&env, loan_id, partial_payment, borrower.clone());
-    
-    // Verificar se o pagamento foi registrado
-    let loan_info = contract.get_loan_info(&env, loan_id);
-    assert_eq!(loan_info.repaid_amount, partial_payment);
-    assert_eq!(loan_info.status, LoanStatus::Active); // Ainda ativo pois não foi totalmente pago
-    
-    // Pagar o restante
-    let remaining = loan_amount - partial_payment;
-    contract.repay_loan(&env, loan_id, remaining, borrower.clone());
-    
-    // Verificar se o empréstimo foi totalmente pago
-    let loan_info = contract.get_loan_info(&env, loan_id);
-    assert_eq!(loan_info.status, LoanStatus::Repaid);
-    assert!(!contract.is_collateral(&env, token_id)); // Colateral deve ser liberado
-}
-
-#[test]
-fn test_liquidate_loan() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let loan_amount = 1000i128;
-    let interest_rate = 500u32; // 5%
-    let duration_days = 1u32; // 1 dia para facilitar o teste
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner.clone());
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        loan_amount,
-        interest_rate,
-        duration_days,
-        borrower.clone()
-    );
-    
-    // Avançar o tempo para simular vencimento
-    env.ledger().set_timestamp(env.ledger().timestamp() + (2 * 24 * 60 * 60)); // 2 dias depois
-    
-    // Liquidar empréstimo
-    contract.liquidate_loan(&env, loan_id, owner);
-    
-    // Verificar se o empréstimo foi liquidado
-    let loan_info = contract.get_loan_info(&env, loan_id);
-    assert_eq!(loan_info.status, LoanStatus::Liquidated);
-    
-    // Verificar se o NFT foi transferido para o owner (liquidator)
-    assert_eq!(contract.owner_of(&env, token_id), Some(owner));
-}
-
-#[test]
-fn test_calculate_interest() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let loan_amount = 1000i128;
-    let interest_rate = 1000u32; // 10%
-    let duration_days = 365u32; // 1 ano
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        loan_amount,
-        interest_rate,
-        duration_days,
-        borrower.clone()
-    );
-    
-    // Avançar o tempo para 1 ano
-    env.ledger().set_timestamp(env.ledger().timestamp() + (365 * 24 * 60 * 60));
-    
-    // Calcular juros
-    let loan_info = contract.get_loan_info(&env, loan_id);
-    let interest = contract.calculate_interest(&env, &loan_info);
-    
-    // Juros esperados: 1000 * 10 * 365 / (100 * 365) = 100
-    assert_eq!(interest, 100);
-}
-
-#[test]
-fn test_get_user_loans() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id1 = 1u32;
-    let token_id2 = 2u32;
-    
-    // Mint NFTs para o borrower
-    contract.mint(&env, borrower.clone(), token_id1, owner);
-    contract.mint(&env, borrower.clone(), token_id2, owner);
-    
-    // Criar dois empréstimos
-    let loan_id1 = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id1,
-        1000i128,
-        500u32,
-        30u32,
-        borrower.clone()
-    );
-    
-    let loan_id2 = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id2,
-        2000i128,
-        600u32,
-        60u32,
-        borrower.clone()
-    );
-    
-    // Verificar empréstimos do usuário
-    let user_loans = contract.get_user_loans(&env, borrower.clone());
-    assert_eq!(user_loans.len(), 2);
-    assert!(user_loans.contains(loan_id1));
-    assert!(user_loans.contains(loan_id2));
-}
-
-#[test]
-#[should_panic(expected = "Not owner of collateral")]
-fn test_create_loan_not_owner() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let other_borrower = Address::generate(&env);
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Tentar criar empréstimo com outro usuário
-    contract.create_loan(
-        &env,
-        other_borrower,
-        token_id,
-        1000i128,
-        500u32,
-        30u32,
-        other_borrower
-    );
-}
-
-#[test]
-#[should_panic(expected = "Token already used as collateral")]
-fn test_create_loan_already_collateral() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Criar primeiro empréstimo
-    contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        1000i128,
-        500u32,
-        30u32,
-        borrower.clone()
-    );
-    
-    // Tentar criar segundo empréstimo com o mesmo token
-    contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        2000i128,
-        600u32,
-        60u32,
-        borrower.clone()
-    );
-}
-
-#[test]
-#[should_panic(expected = "Not the borrower")]
-fn test_repay_loan_not_borrower() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let other_user = Address::generate(&env);
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner);
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        1000i128,
-        500u32,
-        30u32,
-        borrower.clone()
-    );
-    
-    // Tentar pagar com outro usuário
-    contract.repay_loan(&env, loan_id, 500i128, other_user);
-}
-
-#[test]
-#[should_panic(expected = "Loan not yet expired")]
-fn test_liquidate_loan_not_expired() {
-    let (env, contract, owner, borrower) = create_contract();
-    let token_id = 1u32;
-    let loan_amount = 1000i128;
-    let interest_rate = 500u32;
-    let duration_days = 30u32; // 30 dias
-    
-    // Mint um NFT para o borrower
-    contract.mint(&env, borrower.clone(), token_id, owner.clone());
-    
-    // Criar empréstimo
-    let loan_id = contract.create_loan(
-        &env,
-        borrower.clone(),
-        token_id,
-        loan_amount,
-        interest_rate,
-        duration_days,
-        borrower.clone()
-    );
-    
-    // Tentar liquidar antes do vencimento
-    contract.liquidate_loan(&env, loan_id, owner);
+    assert!(client.is_collateral(&1));
+    assert_eq!(client.owner_of(&1), client.address);
+
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.borrower, borrower);
+    assert_eq!(loan.token_id, 1);
+    assert_eq!(loan.amount, 1000);
+    assert_eq!(loan.interest_rate, 500);
+    assert_eq!(loan.duration_days, 30);
+    assert_eq!(loan.status, LoanStatus::Active);
+    assert_eq!(loan.repaid, 0);
+
+    let token = TokenClient::new(&e, &client.payment_token());
+    assert_eq!(token.balance(&borrower), 1000);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS - 1000);
+}
+
+#[test]
+fn create_loan_fails_without_lender_auth() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+
+    let result = client
+        .mock_auths(&[MockAuth {
+            address: &borrower,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "create_loan",
+                args: (&borrower, 1u32, 1000i128, 500u32, 30u32, &borrower).into_val(&e),
+                sub_invokes: &[],
+            },
+        }])
+        .try_create_loan(&borrower, &1, &1000, &500, &30, &borrower);
+    assert!(result.is_err());
+    assert_eq!(client.owner_of(&1), borrower);
+    assert_eq!(TokenClient::new(&e, &client.payment_token()).balance(&borrower), 0);
+}
+
+#[test]
+fn repay_loan_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.repay_loan(&loan_id, &500, &borrower);
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.repaid, 500);
+    assert_eq!(loan.status, LoanStatus::Active);
+
+    client.repay_loan(&loan_id, &500, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert!(!client.is_collateral(&1));
+    assert_eq!(client.owner_of(&1), client.address);
+}
+
+#[test]
+fn reclaim_collateral_returns_escrowed_nft_after_repayment() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.owner_of(&1), client.address);
+    assert!(client.try_reclaim_collateral(&loan_id, &1, &borrower).is_err());
+
+    client.repay_loan(&loan_id, &1000, &borrower);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (Symbol::new(&e, "collateral"), symbol_short!("reclaimed"), loan_id, 4u32).into_val(&e),
+            client.get_loan_info(&loan_id).into_val(&e)
+        )
+    );
+    assert_eq!(client.owner_of(&1), borrower);
+    assert_eq!(client.balance(&borrower), 1);
+}
+
+#[test]
+fn reclaim_collateral_needs_only_borrower_auth() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+
+    client
+        .mock_auths(&[MockAuth {
+            address: &borrower,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "reclaim_collateral",
+                args: (loan_id, 1u32, &borrower).into_val(&e),
+                sub_invokes: &[],
+            },
+        }])
+        .reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(client.owner_of(&1), borrower);
+    assert_eq!(client.get_owner_token_id(&borrower, &0), 1);
+    assert_eq!(client.balance(&client.address), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #34)")]
+fn reclaim_collateral_fails_once_reclaimed() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+}
+
+#[test]
+fn claim_released_collateral_waits_for_release_delay() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_collateral_release_delay(&owner, &3600);
+    assert_eq!(client.collateral_release_delay(), 3600);
+    e.ledger().set_timestamp(100);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+
+    e.ledger().set_timestamp(3699);
+    assert_eq!(
+        client.try_claim_released_collateral(&loan_id),
+        Err(Ok(soroban_sdk::Error::from_contract_error(37)))
+    );
+    assert_eq!(
+        client.try_reclaim_collateral(&loan_id, &1, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(37)))
+    );
+    assert_eq!(client.owner_of(&1), client.address);
+
+    e.ledger().set_timestamp(3700);
+    client.claim_released_collateral(&loan_id);
+    assert_eq!(client.owner_of(&1), borrower);
+}
+
+#[test]
+fn loan_counter_tracks_every_created_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    assert_eq!(client.next_loan_id(), 1);
+    assert_eq!(client.total_loans(), 0);
+
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    assert_eq!(first, 1);
+    client.repay_loan(&first, &500, &borrower);
+    assert_eq!(client.next_loan_id(), 2);
+    let second = mint_and_borrow(&client, &owner, &borrower, 2, 500);
+    let third = mint_and_borrow(&client, &owner, &borrower, 3, 500);
+    assert_eq!((second, third), (2, 3));
+    assert_eq!(client.next_loan_id(), 4);
+    assert_eq!(client.total_loans(), 3);
+}
+
+#[test]
+fn close_loan_removes_repaid_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(
+        client.try_close_loan(&loan_id, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(39)))
+    );
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert_eq!(
+        client.try_close_loan(&loan_id, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(40)))
+    );
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+
+    client.close_loan(&loan_id, &borrower);
+    assert_eq!(
+        client.try_get_loan_info(&loan_id),
+        Err(Ok(soroban_sdk::Error::from_contract_error(8)))
+    );
+    assert_eq!(client.get_user_loans(&borrower).len(), 0);
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Repaid, &0, &10).len(), 0);
+    assert_eq!(client.original_terms(&loan_id).amount, 1000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn close_loan_requires_borrower_or_lender() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+
+    client.close_loan(&loan_id, &Address::generate(&e));
+}
+
+#[test]
+fn mint_next_assigns_sequential_ids() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let user = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(client.mint_next(&user, &owner), 1);
+    assert_eq!(client.mint_next(&user, &owner), 2);
+    assert_eq!(client.mint_next(&owner, &owner), 3);
+    assert_eq!(client.owner_of(&2), user);
+    assert_eq!(client.owner_of(&3), owner);
+    assert_eq!(client.balance(&user), 2);
+    assert!(client.try_mint_next(&user, &user).is_err());
+}
+
+#[test]
+fn mint_next_skips_ids_taken_through_mint() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let user = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.mint(&user, &2, &owner);
+    client.mint(&user, &3, &owner);
+    assert_eq!(client.mint_next(&user, &owner), 1);
+    assert_eq!(client.mint_next(&user, &owner), 4);
+    assert_eq!(client.mint_next(&user, &owner), 5);
+    assert_eq!(client.balance(&user), 5);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #1000)")]
+fn mint_next_fails_while_paused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.pause(&owner);
+
+    client.mint_next(&owner, &owner);
+}
+
+#[test]
+fn emergency_withdraw_moves_stuck_assets_while_paused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let rescue = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.mint(&client.address, &2, &owner);
+    let token = client.payment_token();
+    StellarAssetClient::new(&e, &token).mint(&client.address, &700);
+    client.pause(&owner);
+
+    client.emergency_withdraw_nft(&owner, &2, &rescue);
+    assert_eq!(client.owner_of(&2), rescue);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (Symbol::new(&e, "emergency"), symbol_short!("nft")).into_val(&e),
+            (2u32, rescue.clone()).into_val(&e)
+        )
+    );
+
+    client.emergency_withdraw_token(&owner, &token, &700, &rescue);
+    assert_eq!(TokenClient::new(&e, &token).balance(&rescue), 700);
+    assert_eq!(TokenClient::new(&e, &token).balance(&client.address), 0);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (Symbol::new(&e, "emergency"), symbol_short!("token")).into_val(&e),
+            (token, 700i128, rescue).into_val(&e)
+        )
+    );
+}
+
+#[test]
+fn emergency_withdraw_keeps_pool_bids_and_fees_of_the_payment_token() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let rescue = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &funded_bidder(&e, &token), &2000);
+    supply_pool(&e, &client, 1000);
+    StellarAssetClient::new(&e, &token.address).mint(&client.address, &700);
+    client.pause(&owner);
+
+    assert_eq!(
+        client.try_emergency_withdraw_token(&owner, &token.address, &701, &rescue),
+        Err(Ok(soroban_sdk::Error::from_contract_error(58)))
+    );
+    client.emergency_withdraw_token(&owner, &token.address, &700, &rescue);
+    assert_eq!(token.balance(&rescue), 700);
+    assert_eq!(token.balance(&client.address), 3000);
+}
+
+#[test]
+fn force_close_loan_as_repaid_returns_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.pause(&owner);
+
+    let reason = symbol_short!("dispute");
+    client.force_close_loan(&loan_id, &LoanStatus::Repaid, &reason, &owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert_eq!(client.owner_of(&1), borrower);
+    assert_eq!(client.collateral_loan_id(&1), None);
+    assert_eq!(client.total_outstanding(), 0);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (symbol_short!("admin"), Symbol::new(&e, "force_closed")).into_val(&e),
+            (loan_id, LoanStatus::Repaid, reason).into_val(&e)
+        )
+    );
+}
+
+#[test]
+fn force_close_loan_as_liquidated_refunds_auction_bid() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &2000);
+    client.pause(&owner);
+
+    client.force_close_loan(&loan_id, &LoanStatus::Liquidated, &symbol_short!("exploit"), &owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+    assert_eq!(client.owner_of(&1), owner);
+    assert_eq!(client.auction(&loan_id), None);
+    assert_eq!(token.balance(&bidder), 5000);
+}
+
+#[test]
+fn force_close_loan_fails_to_liquidate_pool_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = pool_loan(&e, &client, &owner, &borrower);
+    client.pause(&owner);
+
+    let reason = symbol_short!("exploit");
+    assert_eq!(
+        client.try_force_close_loan(&loan_id, &LoanStatus::Liquidated, &reason, &owner),
+        Err(Ok(soroban_sdk::Error::from_contract_error(57)))
+    );
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+    assert_eq!(client.pool_balance(), 0);
+
+    client.force_close_loan(&loan_id, &LoanStatus::Repaid, &reason, &owner);
+    assert_eq!(client.owner_of(&1), borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn force_close_loan_fails_for_non_owner() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.pause(&owner);
+
+    client.force_close_loan(&loan_id, &LoanStatus::Repaid, &symbol_short!("dispute"), &borrower);
+}
+
+#[test]
+fn emergency_withdraw_nft_fails_for_open_loan_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.pause(&owner);
+    assert_eq!(
+        client.try_emergency_withdraw_nft(&owner, &1, &owner),
+        Err(Ok(soroban_sdk::Error::from_contract_error(24)))
+    );
+    assert_eq!(client.owner_of(&1), client.address);
+
+    let reason = symbol_short!("rescue");
+    client.force_close_loan(&loan_id, &LoanStatus::Liquidated, &reason, &owner);
+    assert_eq!(client.owner_of(&1), owner);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #1001)")]
+fn emergency_withdraw_fails_while_unpaused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.emergency_withdraw_nft(&owner, &1, &owner);
+}
+
+#[test]
+fn default_interest_rate_applies_to_new_loans_only() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_default_interest_rate(&owner, &700);
+    assert_eq!(client.default_interest_rate(), Some(700));
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    mint(&client, &owner, &borrower, 3);
+    let old = client.create_loan(&borrower, &1, &500, &USE_DEFAULT_RATE, &30, &borrower);
+    let fixed = client.create_loan(&borrower, &2, &500, &400, &30, &borrower);
+
+    client.set_default_interest_rate(&owner, &900);
+    let new = client.create_loan(&borrower, &3, &500, &USE_DEFAULT_RATE, &30, &borrower);
+    assert_eq!(client.get_loan_info(&old).interest_rate, 700);
+    assert_eq!(client.get_loan_info(&fixed).interest_rate, 400);
+    assert_eq!(client.get_loan_info(&new).interest_rate, 900);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #41)")]
+fn create_loan_fails_for_default_rate_when_unset() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+
+    client.create_loan(&borrower, &1, &500, &USE_DEFAULT_RATE, &30, &borrower);
+}
+
+#[test]
+fn collateral_loan_id_resolves_token_to_its_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 2, 500);
+    mint(&client, &owner, &borrower, 3);
+    assert_eq!(client.collateral_loan_id(&2), Some(loan_id));
+    assert_eq!(client.collateral_loan_id(&3), None);
+
+    client.repay_loan(&loan_id, &500, &borrower);
+    assert_eq!(client.collateral_loan_id(&2), None);
+}
+
+#[test]
+fn get_payments_lists_each_payment_in_order() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.get_payments(&loan_id).len(), 0);
+
+    client.repay_loan(&loan_id, &100, &borrower);
+    e.ledger().set_timestamp(60);
+    client.repay_loan(&loan_id, &200, &borrower);
+    e.ledger().set_timestamp(120);
+    client.repay_loan(&loan_id, &300, &borrower);
+    assert_eq!(
+        client.get_payments(&loan_id),
+        vec![&e, (0u64, 100i128), (60, 200), (120, 300)]
+    );
+}
+
+#[test]
+fn collateralized_tokens_lists_locked_tokens() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    mint_and_borrow(&client, &owner, &borrower, 2, 500);
+    mint(&client, &owner, &borrower, 3);
+    assert_eq!(client.collateralized_tokens(&0, &10), vec![&e, 1, 2]);
+    assert_eq!(client.collateralized_tokens(&1, &10), vec![&e, 2]);
+
+    client.repay_loan(&first, &500, &borrower);
+    assert_eq!(client.collateralized_tokens(&0, &10), vec![&e, 2]);
+}
+
+#[test]
+fn get_loan_summary_matches_individual_getters() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    for timestamp in [10 * 24 * 60 * 60, 31 * 24 * 60 * 60] {
+        e.ledger().set_timestamp(timestamp);
+        assert_eq!(
+            client.get_loan_summary(&loan_id),
+            LoanSummary {
+                loan: client.get_loan_info(&loan_id),
+                accrued_interest: client.calculate_interest(&loan_id),
+                outstanding_balance: client.outstanding_balance(&loan_id),
+                due_at: client.loan_detail(&loan_id).due_at,
+                liquidatable: client.has_liquidatable_loan(&borrower),
+            }
+        );
+    }
+    assert!(client.get_loan_summary(&loan_id).liquidatable);
+    assert_eq!(client.get_loan_summary(&loan_id).due_at, 30 * 24 * 60 * 60);
+}
+
+#[test]
+fn borrower_stats_count_how_loans_ended() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    assert_eq!(client.borrower_stats(&borrower), BorrowerStats::default());
+    let repaid = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    let liquidated = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    client.repay_loan(&repaid, &500, &borrower);
+
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    client.mark_defaulted(&liquidated, &owner);
+    client.start_liquidation(&liquidated);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&liquidated, &owner);
+    assert_eq!(
+        client.borrower_stats(&borrower),
+        BorrowerStats { repaid: 1, defaulted: 1, liquidated: 1 }
+    );
+    assert_eq!(client.borrower_stats(&owner), BorrowerStats::default());
+}
+
+#[test]
+fn get_stats_tracks_loans_through_their_lifecycle() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let repaid = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    let liquidated = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    client.repay_loan(&repaid, &500, &borrower);
+
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    mint_and_borrow(&client, &owner, &borrower, 3, 700);
+    client.start_liquidation(&liquidated);
+    assert_eq!(
+        client.get_stats(),
+        ContractStats {
+            total_loans: 3,
+            active_loans: 1,
+            total_outstanding: 1700,
+            collateral_locked: 2,
+            liquidations: 0,
+        }
+    );
+
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&liquidated, &owner);
+    assert_eq!(
+        client.get_stats(),
+        ContractStats {
+            total_loans: 3,
+            active_loans: 1,
+            total_outstanding: 700,
+            collateral_locked: 1,
+            liquidations: 1,
+        }
+    );
+}
+
+#[test]
+fn bump_loan_ttl_keeps_loan_live() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.set_loan_ttl(&owner, &100_000);
+    assert_eq!(client.loan_ttl(), 100_000);
+
+    client.bump_loan_ttl(&loan_id);
+    let key = (symbol_short!("loan"), loan_id);
+    let ttl = e.as_contract(&client.address, || e.storage().persistent().get_ttl(&key));
+    assert_eq!(ttl, 100_000);
+
+    e.as_contract(&client.address, || e.storage().instance().extend_ttl(100_000, 100_000));
+    e.ledger().set_sequence_number(50_000);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 1000);
+}
+
+fn supply_pool(e: &Env, client: &LendingNFTClient, amount: i128) -> Address {
+    let lender = Address::generate(e);
+    StellarAssetClient::new(e, &client.payment_token()).mint(&lender, &amount);
+    client.deposit(&lender, &amount);
+    lender
+}
+
+/// Open a pool loan of the whole 1000 supplied to the pool.
+fn pool_loan(e: &Env, client: &LendingNFTClient, owner: &Address, borrower: &Address) -> u32 {
+    client.set_rate_model(owner, &RateModel { base_rate: 1000, max_rate: 1000 });
+    supply_pool(e, client, 1000);
+    mint(client, owner, borrower, 1);
+    client.create_pool_loan(borrower, &1, &1000, &30)
+}
+
+#[test]
+fn borrow_rate_follows_utilization() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_rate_model(&owner, &RateModel { base_rate: 500, max_rate: 2500 });
+    supply_pool(&e, &client, 1000);
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    assert_eq!(client.utilization_rate(), 0);
+    assert_eq!(client.borrow_rate(), 500);
+
+    let first = client.create_pool_loan(&borrower, &1, &500, &30);
+    assert_eq!(client.get_loan_info(&first).interest_rate, 500);
+    assert_eq!(client.get_loan_info(&first).lender, client.address);
+    assert_eq!(client.total_borrowed(), 500);
+    assert_eq!(client.utilization_rate(), 5000);
+    assert_eq!(client.borrow_rate(), 1500);
+
+    let second = client.create_pool_loan(&borrower, &2, &500, &30);
+    assert_eq!(client.get_loan_info(&second).interest_rate, 1500);
+    assert_eq!(client.utilization_rate(), 10_000);
+    assert_eq!(client.borrow_rate(), 2500);
+
+    client.repay_loan(&first, &500, &borrower);
+    assert_eq!(client.total_borrowed(), 500);
+    assert_eq!(client.utilization_rate(), 5000);
+}
+
+#[test]
+fn pool_shares_earn_interest_pro_rata() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    client.set_rate_model(&owner, &RateModel { base_rate: 1000, max_rate: 1000 });
+    let alice = supply_pool(&e, &client, 3000);
+    let bob = supply_pool(&e, &client, 1000);
+    assert_eq!(client.shares_of(&alice), 3000);
+    assert_eq!(client.shares_of(&bob), 1000);
+    assert_eq!(client.total_shares(), 4000);
+    assert_eq!(client.pool_balance(), 4000);
+
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_pool_loan(&borrower, &1, &1000, &365);
+    assert_eq!(token.balance(&borrower), 1000);
+    assert_eq!(client.pool_balance(), 3000);
+    assert_eq!(
+        client.try_withdraw(&alice, &3000),
+        Err(Ok(soroban_sdk::Error::from_contract_error(48)))
+    );
+
+    e.ledger().set_timestamp(365 * 86400);
+    let interest = client.calculate_interest(&loan_id);
+    assert!(interest > 0);
+    StellarAssetClient::new(&e, &token.address).mint(&borrower, &interest);
+    client.repay_loan(&loan_id, &(1000 + interest), &borrower);
+    assert_eq!(client.total_borrowed(), 0);
+    assert_eq!(client.pool_balance(), 4000 + interest);
+    assert_eq!(token.balance(&client.address), 4000 + interest);
+
+    let to_bob = client.withdraw(&bob, &1000);
+    assert_eq!(to_bob, (4000 + interest) / 4);
+    assert_eq!(token.balance(&bob), to_bob);
+    let to_alice = client.withdraw(&alice, &3000);
+    assert_eq!(to_alice, 4000 + interest - to_bob);
+    assert_eq!(client.pool_balance(), 0);
+    assert_eq!(client.total_shares(), 0);
+}
+
+#[test]
+fn liquidating_pool_loan_sells_collateral_to_keeper() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let keeper = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    client.set_rate_model(&owner, &RateModel { base_rate: 1000, max_rate: 1000 });
+    client.add_keeper(&owner, &keeper);
+    fund(&e, &client, &keeper, 2000);
+    supply_pool(&e, &client, 1000);
+    mint(&client, &owner, &borrower, 1);
+    client.set_collateral_value(&owner, &1, &2000);
+    let loan_id = client.create_pool_loan(&borrower, &1, &1000, &30);
+
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    client.mark_defaulted(&loan_id, &keeper);
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &keeper);
+
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+    assert_eq!(client.owner_of(&1), keeper);
+    assert_eq!(client.total_borrowed(), 0);
+    assert_eq!(client.pool_balance(), 1008);
+    assert_eq!(token.balance(&client.address), 1008);
+    assert_eq!(token.balance(&borrower), 1992);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #49)")]
+fn withdraw_fails_beyond_held_shares() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let lender = supply_pool(&e, &client, 1000);
+
+    client.withdraw(&lender, &1001);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #48)")]
+fn create_pool_loan_fails_beyond_pool_supply() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_rate_model(&owner, &RateModel { base_rate: 500, max_rate: 2500 });
+    supply_pool(&e, &client, 1000);
+    mint(&client, &owner, &borrower, 1);
+
+    client.create_pool_loan(&borrower, &1, &1001, &30);
+}
+
+#[test]
+fn loans_get_independent_records() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let second = mint_and_borrow(&client, &owner, &borrower, 2, 2000);
+    assert_eq!((first, second), (1, 2));
+
+    client.repay_loan(&first, &1000, &borrower);
+    assert_eq!(client.get_loan_info(&first).status, LoanStatus::Repaid);
+    assert!(!client.is_collateral(&1));
+
+    let loan = client.get_loan_info(&second);
+    assert_eq!(loan.token_id, 2);
+    assert_eq!(loan.amount, 2000);
+    assert_eq!(loan.status, LoanStatus::Active);
+    assert!(client.is_collateral(&2));
+}
+
+#[test]
+fn get_loans_by_status_filters_and_pages() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    for token_id in 1..=4 {
+        mint_and_borrow(&client, &owner, &borrower, token_id, 1000);
+    }
+    client.repay_loan(&2, &1000, &borrower);
+    client.repay_loan(&4, &1000, &borrower);
+
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Active, &0, &10), vec![&e, 1, 3]);
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Repaid, &0, &10), vec![&e, 2, 4]);
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Repaid, &1, &1), vec![&e, 4]);
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Active, &2, &10), vec![&e]);
+}
+
+#[test]
+fn loans_created_between_filters_by_creation_time() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    for token_id in 1..=4 {
+        e.ledger().set_timestamp(token_id as u64 * 100);
+        mint_and_borrow(&client, &owner, &borrower, token_id, 1000);
+    }
+
+    assert_eq!(client.loans_created_between(&200, &300, &10), vec![&e, 2, 3]);
+    assert_eq!(client.loans_created_between(&150, &1000, &2), vec![&e, 2, 3]);
+    assert_eq!(client.loans_created_between(&0, &99, &10), vec![&e]);
+    assert_eq!(client.loans_created_between(&400, &400, &10), vec![&e, 4]);
+    assert_eq!(client.loans_created_between(&301, &1000, &10), vec![&e, 4]);
+    assert_eq!(client.loans_created_between(&401, &1000, &10), vec![&e]);
+}
+
+#[test]
+fn lender_interest_earned_tracks_interest_paid() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.lender_interest_earned(&owner), 0);
+
+    e.ledger().set_timestamp(30 * 86400);
+    let interest = client.calculate_interest(&loan_id);
+    assert!(interest > 0);
+    fund(&e, &client, &borrower, interest);
+    client.repay_loan(&loan_id, &(1000 + interest), &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS + interest);
+    assert_eq!(client.lender_interest_earned(&owner), interest);
+    assert_eq!(client.lender_interest_earned(&borrower), 0);
+}
+
+#[test]
+fn repay_loan_for_lets_third_party_repay() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let friend = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    StellarAssetClient::new(&e, &token.address).mint(&friend, &1500);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    let repayment = client.repay_loan_for(&loan_id, &1500, &friend);
+    assert_eq!(repayment, Repayment { fees: 0, interest: 0, principal: 1000 });
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Repaid);
+    assert_eq!(loan.borrower, borrower);
+    assert_eq!(token.balance(&friend), 500);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS);
+    assert_eq!(token.balance(&borrower), 1000);
+
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(client.owner_of(&1), borrower);
+}
+
+#[test]
+fn payoff_at_projects_interest_and_late_fees() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_late_fee_rate(&owner, &3650);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let day = 24 * 60 * 60;
+
+    assert_eq!(client.payoff_at(&loan_id, &(30 * day)), 1004);
+    assert_eq!(client.payoff_at(&loan_id, &(37 * day)), 1011);
+    assert_eq!(client.outstanding_balance(&loan_id), 1000);
+
+    e.ledger().set_timestamp(37 * day);
+    assert_eq!(client.outstanding_balance(&loan_id), 1011);
+    assert_eq!(
+        client.try_payoff_at(&loan_id, &(30 * day)),
+        Err(Ok(soroban_sdk::Error::from_contract_error(55)))
+    );
+}
+
+#[test]
+fn calculate_interest_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &365, &borrower);
+
+    e.ledger().set_timestamp(365 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 100);
+    assert_eq!(client.outstanding_balance(&loan_id), 1100);
+
+    e.ledger().set_timestamp(400 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 100);
+}
+
+#[test]
+fn get_user_loans_lists_every_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    let first = client.create_loan(&borrower, &1, &1000, &500, &30, &borrower);
+    let second = client.create_loan(&borrower, &2, &2000, &600, &60, &borrower);
+
+    assert_eq!(client.get_user_loans(&borrower), vec![&e, first, second]);
+}
+
+#[test]
+fn interest_rate_is_in_basis_points() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(client.quote_loan(&10_000, &1, &365), 10_001);
+    assert_eq!(client.quote_loan(&1000, &10_000, &365), 2000);
+    assert_eq!(client.quote_loan(&1000, &150, &365), 1015);
+    assert_eq!(client.quote_loan(&1000, &1, &365), 1000);
+
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &10_000, &365, &borrower);
+    e.ledger().set_timestamp(365 * 24 * 60 * 60 / 2);
+    assert_eq!(client.calculate_interest(&loan_id), 500);
+}
+
+#[test]
+fn quote_loan_matches_full_term_interest() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(client.quote_loan(&1000, &1000, &365), 1100);
+    assert_eq!(client.quote_loan(&1_000_000, &3650, &30), 1_030_000);
+    assert_eq!(client.quote_loan(&1000, &500, &0), 1000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #3)")]
+fn create_loan_fails_when_not_owner_of_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint(&client, &owner, &borrower, 1);
+    client.create_loan(&other, &1, &1000, &500, &30, &other);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #4)")]
+fn create_loan_fails_when_already_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.create_loan(&borrower, &1, &2000, &600, &60, &borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #53)")]
+fn repay_loan_rejects_partial_payment_below_minimum() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_min_repayment(&owner, &100);
+    assert_eq!(client.min_repayment(), 100);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &100, &borrower);
+
+    client.repay_loan(&loan_id, &99, &borrower);
+}
+
+#[test]
+fn repay_loan_accepts_final_payoff_below_minimum() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_min_repayment(&owner, &100);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &950, &borrower);
+
+    client.repay_loan(&loan_id, &50, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+}
+
+#[test]
+fn approved_operator_creates_and_repays_for_borrower() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let operator = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    fund(&e, &client, &operator, 1000);
+    mint(&client, &owner, &borrower, 1);
+    client.approve_operator(&borrower, &operator);
+    assert!(client.is_approved_operator(&borrower, &operator));
+
+    let loan_id = client.create_loan(&borrower, &1, &1000, &500, &30, &operator);
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.borrower, borrower);
+    assert_eq!(client.owner_of(&1), client.address);
+    assert_eq!(client.get_user_loans(&borrower), vec![&e, loan_id]);
+
+    client.repay_loan(&loan_id, &1000, &operator);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #6)")]
+fn revoked_operator_cannot_create_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let operator = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    client.approve_operator(&borrower, &operator);
+    client.revoke_operator(&borrower, &operator);
+    assert!(!client.is_approved_operator(&borrower, &operator));
+
+    client.create_loan(&borrower, &1, &1000, &500, &30, &operator);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #6)")]
+fn repay_loan_fails_when_not_borrower() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &500, &other);
+}
+
+#[test]
+fn liquidation_penalty_reduces_borrower_surplus() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_liquidation_penalty(&owner, &500);
+    assert_eq!(client.liquidation_penalty(), 500);
+    let mut loan = client.get_loan_info(&mint_and_borrow(&client, &owner, &borrower, 1, 1000));
+
+    let to_borrower =
+        e.as_contract(&client.address, || LendingNFT::settle_surplus(&e, &mut loan, 2000));
+    assert_eq!(to_borrower, 1900);
+    assert_eq!(client.protocol_fees(), 100);
+}
+
+#[test]
+fn liquidation_penalty_is_fixed_at_loan_creation() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.set_liquidation_penalty(&owner, &500);
+    let mut loan = client.get_loan_info(&loan_id);
+
+    let to_borrower =
+        e.as_contract(&client.address, || LendingNFT::settle_surplus(&e, &mut loan, 2000));
+    assert_eq!(to_borrower, 2000);
+    assert_eq!(client.protocol_fees(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #9)")]
+fn set_liquidation_penalty_fails_above_max_bps() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_liquidation_penalty(&owner, &10_001);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn set_liquidation_penalty_fails_when_not_owner() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_liquidation_penalty(&other, &500);
+}
+
+#[test]
+fn operational_status_reflects_flags() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(
+        client.operational_status(),
+        OperationalStatus {
+            paused: false,
+            lending_paused: false,
+            borrowing_enabled: true,
+            interest_frozen: false,
+        }
+    );
+
+    client.pause(&owner);
+    assert_eq!(
+        client.operational_status(),
+        OperationalStatus {
+            paused: true,
+            lending_paused: false,
+            borrowing_enabled: true,
+            interest_frozen: false,
+        }
+    );
+
+    client.set_freeze_pauses_interest(&owner, &true);
+    assert!(client.operational_status().interest_frozen);
+
+    client.unpause(&owner);
+    client.pause_lending(&owner);
+    assert_eq!(
+        client.operational_status(),
+        OperationalStatus {
+            paused: false,
+            lending_paused: true,
+            borrowing_enabled: false,
+            interest_frozen: false,
+        }
+    );
+}
+
+fn early_repay_setup<'a>(e: &Env, discount_bps: u32) -> (LendingNFTClient<'a>, Address, u32) {
+    let owner = Address::generate(e);
+    let borrower = Address::generate(e);
+    let client = create_client(e, &owner);
+    fund(e, &client, &borrower, 1_370);
+
+    client.set_early_repay_discount(&owner, &discount_bps);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1_000_000, &1000, &20, &borrower);
+    (client, borrower, loan_id)
+}
+
+#[test]
+fn accrue_compounds_interest_over_simple_interest() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_compounding_enabled(&owner, &true);
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    let simple = client.create_loan(&borrower, &1, &1_000_000, &3650, &30, &borrower);
+    let compound = client.create_loan(&borrower, &2, &1_000_000, &3650, &30, &borrower);
+
+    let day = 24 * 60 * 60;
+    e.ledger().set_timestamp(day);
+    assert_eq!(client.accrue(&compound), 1000);
+    assert_eq!(client.calculate_interest(&simple), 1000);
+    for n in 2..=30 {
+        e.ledger().set_timestamp(n * day);
+        client.accrue(&compound);
+    }
+    assert_eq!(client.calculate_interest(&simple), 30_000);
+    assert_eq!(client.calculate_interest(&compound), 30_435);
+    assert_eq!(client.get_loan_info(&compound).last_accrual_timestamp, 30 * day);
+    assert_eq!(client.outstanding_balance(&compound), 1_030_435);
+
+    client.repay_loan(&compound, &1_030_435, &borrower);
+    assert_eq!(client.get_loan_info(&compound).status, LoanStatus::Repaid);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #38)")]
+fn accrue_fails_unless_compounding_enabled() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.accrue(&loan_id);
+}
+
+#[test]
+fn early_repay_discount_applies_before_half_duration() {
+    let e = Env::default();
+    let (client, borrower, loan_id) = early_repay_setup(&e, 5000);
+
+    e.ledger().set_timestamp(10 * 24 * 60 * 60 - 1);
+    assert_eq!(client.calculate_interest(&loan_id), 2739);
+    assert_eq!(client.outstanding_balance(&loan_id), 1_001_370);
+
+    client.repay_loan(&loan_id, &1_001_370, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert!(!client.is_collateral(&1));
+}
+
+#[test]
+fn early_repay_discount_stops_at_half_duration() {
+    let e = Env::default();
+    let (client, borrower, loan_id) = early_repay_setup(&e, 5000);
+
+    e.ledger().set_timestamp(10 * 24 * 60 * 60);
+    assert_eq!(client.outstanding_balance(&loan_id), 1_002_739);
+
+    client.repay_loan(&loan_id, &1_001_370, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+    assert_eq!(client.outstanding_balance(&loan_id), 1369);
+}
+
+#[test]
+fn early_repay_discount_disabled_at_zero_bps() {
+    let e = Env::default();
+    let (client, _, loan_id) = early_repay_setup(&e, 0);
+
+    e.ledger().set_timestamp(2 * 24 * 60 * 60);
+    assert_eq!(client.outstanding_balance(&loan_id), 1_000_547);
+}
+
+#[test]
+fn repayment_waterfall_settles_fees_then_interest_then_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    fund(&e, &client, &borrower, 110_000);
+
+    client.set_origination_fee(&owner, &100);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1_000_000, &1000, &365, &borrower);
+
+    e.ledger().set_timestamp(365 * 24 * 60 * 60);
+    assert_eq!(client.outstanding_balance(&loan_id), 1_110_000);
+
+    let repayment = client.repay_loan(&loan_id, &50_000, &borrower);
+    assert_eq!(repayment, Repayment { fees: 10_000, interest: 40_000, principal: 0 });
+    assert_eq!(client.outstanding_balance(&loan_id), 1_060_000);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+    assert_eq!(client.protocol_fees(), 10_000);
+    assert_eq!(token.balance(&client.address), 10_000);
+
+    let repayment = client.repay_loan(&loan_id, &1_060_000, &borrower);
+    assert_eq!(repayment, Repayment { fees: 0, interest: 60_000, principal: 1_000_000 });
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+}
+
+#[test]
+fn late_fees_accrue_past_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_late_fee_rate(&owner, &3650);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &365, &borrower);
+
+    e.ledger().set_timestamp(365 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 100);
+
+    e.ledger().set_timestamp(375 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 110);
+    assert_eq!(client.outstanding_balance(&loan_id), 1110);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #10)")]
+fn create_loan_fails_with_price_liquidation_and_no_oracle() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_price_liquidation(&owner, &true);
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+}
+
+#[test]
+fn create_loan_with_price_liquidation_and_oracle_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    MockOracleClient::new(&e, &oracle).set_price(&1, &2000);
+
+    client.set_price_liquidation(&owner, &true);
+    client.set_oracle(&owner, &oracle);
+    assert_eq!(client.oracle(), Some(oracle));
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+}
+
+#[test]
+fn liquidatable_loans_detailed_returns_only_overdue_loans() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    mint(&client, &owner, &borrower, 3);
+    mint(&client, &owner, &borrower, 4);
+    client.create_loan(&borrower, &1, &1000, &1000, &1, &borrower);
+    client.create_loan(&borrower, &2, &1000, &1000, &30, &borrower);
+    client.create_loan(&borrower, &3, &1000, &1000, &1, &borrower);
+    client.create_loan(&borrower, &4, &1000, &1000, &1, &borrower);
+    client.repay_loan(&3, &1000, &borrower);
+
+    e.ledger().set_timestamp(2 * 24 * 60 * 60);
+    client.mark_defaulted(&4, &owner);
+    let details = client.liquidatable_loans_detailed(&0, &10);
+    assert_eq!(details.len(), 2);
+    assert_eq!(details.get(1).unwrap().loan_id, 4);
+
+    let detail = details.get(0).unwrap();
+    assert_eq!(detail.loan_id, 1);
+    assert_eq!(detail.loan, client.get_loan_info(&1));
+    assert_eq!(detail.due_at, 24 * 60 * 60);
+    assert_eq!(detail.outstanding_balance, client.outstanding_balance(&1));
+    assert_eq!(client.loan_detail(&1), detail);
+
+    let rest = client.liquidatable_loans_detailed(&1, &10);
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest.get(0).unwrap().loan.status, LoanStatus::Defaulted);
+}
+
+#[test]
+fn get_user_loans_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    mint_and_borrow(&client, &owner, &other, 2, 1000);
+    let third = mint_and_borrow(&client, &owner, &borrower, 3, 2000);
+
+    assert_eq!(client.get_user_loans(&borrower), vec![&e, first, third]);
+    assert_eq!(client.user_loan_count(&borrower), 2);
+    assert_eq!(client.user_loan_count(&Address::generate(&e)), 0);
+}
+
+#[test]
+fn get_user_loans_paged_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    for token_id in 1..=5 {
+        mint_and_borrow(&client, &owner, &borrower, token_id, 1000);
+    }
+
+    assert_eq!(client.get_user_loans_paged(&borrower, &0, &2), vec![&e, 1, 2]);
+    assert_eq!(client.get_user_loans_paged(&borrower, &4, &2), vec![&e, 5]);
+    assert_eq!(client.get_user_loans_paged(&borrower, &5, &2), vec![&e]);
+    assert_eq!(client.get_user_loans_paged(&borrower, &10, &2), vec![&e]);
+    assert_eq!(client.get_user_loans_paged(&borrower, &0, &u32::MAX).len(), 5);
+}
+
+#[test]
+fn health_factor_tracks_time_to_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.health_factor(&loan_id), 10_000);
+
+    e.ledger().set_timestamp(15 * 24 * 60 * 60);
+    assert_eq!(client.health_factor(&loan_id), 5_000);
+
+    e.ledger().set_timestamp(30 * 24 * 60 * 60);
+    assert_eq!(client.health_factor(&loan_id), 0);
+
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    assert_eq!(client.health_factor(&loan_id), 0);
+}
+
+#[test]
+fn original_terms_survive_extension() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    e.ledger().set_timestamp(1000);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let terms = LoanTerms {
+        borrower: borrower.clone(),
+        token_id: 1,
+        amount: 1000,
+        interest_rate: 500,
+        duration_days: 30,
+        created_at: 1000,
+        fees: 0,
+    };
+    assert_eq!(client.original_terms(&loan_id), terms);
+
+    client.extend_loan(&owner, &loan_id, &15);
+    assert_eq!(client.get_loan_info(&loan_id).duration_days, 45);
+    assert_eq!(client.original_terms(&loan_id), terms);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn extend_loan_fails_when_not_owner() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.extend_loan(&borrower, &loan_id, &15);
+}
+
+#[test]
+fn create_loan_at_max_ltv_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(client.max_ltv(), 5000);
+    client.mint(&borrower, &1, &owner);
+    client.set_collateral_value(&owner, &1, &1000);
+    assert_eq!(client.collateral_value(&1), 1000);
+
+    let loan_id = client.create_loan(&borrower, &1, &500, &500, &30, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 500);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #11)")]
+fn create_loan_fails_above_max_ltv() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.mint(&borrower, &1, &owner);
+    client.set_collateral_value(&owner, &1, &1000);
+    client.create_loan(&borrower, &1, &501, &500, &30, &borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #11)")]
+fn create_loan_fails_for_unvalued_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.mint(&borrower, &1, &owner);
+    client.create_loan(&borrower, &1, &1, &500, &30, &borrower);
+}
+
+#[test]
+fn set_max_ltv_works() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_max_ltv(&owner, &6000);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("admin"), symbol_short!("max_ltv")).into_val(&e),
+                6000u32.into_val(&e)
+            )
+        ]
+    );
+    assert_eq!(client.max_ltv(), 6000);
+
+    client.mint(&borrower, &1, &owner);
+    client.set_collateral_value(&owner, &1, &1000);
+    assert_eq!(client.max_borrowable(&1), 600);
+    let loan_id = client.create_loan(&borrower, &1, &600, &500, &30, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 600);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #9)")]
+fn set_max_ltv_fails_above_max_bps() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_max_ltv(&owner, &10_001);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn set_max_ltv_fails_for_non_owner() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_max_ltv(&other, &6000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #7)")]
+fn repay_closed_loan_reverts_by_default() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    client.repay_loan(&loan_id, &100, &borrower);
+}
+
+#[test]
+fn repay_closed_loan_is_refunded_when_enabled() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_refund_closed_repayments(&owner, &true);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1000, &borrower);
+
+    let repayment = client.repay_loan(&loan_id, &100, &borrower);
+    assert_eq!(repayment, Repayment { fees: 0, interest: 0, principal: 0 });
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Repaid);
+    assert_eq!(loan.repaid, 1000);
+}
+
+#[contract]
+struct MockYieldSource;
+
+#[contractimpl]
+impl MockYieldSource {
+    pub fn __constructor(e: Env, token: Address) {
+        e.storage().instance().set(&symbol_short!("token"), &token);
+    }
+
+    pub fn claim_yield(e: Env, _token_id: u32, to: Address) -> i128 {
+        let token: Address = e.storage().instance().get(&symbol_short!("token")).unwrap();
+        TokenClient::new(&e, &token).transfer(&e.current_contract_address(), &to, &250);
+        250
+    }
+}
+
+#[test]
+fn claim_collateral_yield_is_credited_to_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    let source = e.register(MockYieldSource, (&token.address,));
+    fund(&e, &client, &source, 1000);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 900);
+    client.set_yield_source(&owner, &1, &source);
+    assert_eq!(client.yield_source(&1), Some(source));
+
+    let repayment = client.claim_collateral_yield(&loan_id);
+    assert_eq!(repayment, Repayment { fees: 0, interest: 0, principal: 250 });
+    assert_eq!(client.get_loan_info(&loan_id).repaid, 250);
+    assert_eq!(client.outstanding_balance(&loan_id), 650);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS - 650);
+
+    for _ in 0..3 {
+        client.claim_collateral_yield(&loan_id);
+    }
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS);
+    assert_eq!(token.balance(&borrower), 1000);
+    assert_eq!(token.balance(&client.address), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #12)")]
+fn claim_collateral_yield_fails_without_yield_source() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.claim_collateral_yield(&loan_id);
+}
+
+#[contract]
+struct MockOracle;
+
+#[contractimpl]
+impl MockOracle {
+    pub fn set_price(e: Env, token_id: u32, price: i128) {
+        e.storage().instance().set(&token_id, &price);
+    }
+
+    pub fn price(e: Env, token_id: u32) -> i128 {
+        e.storage().instance().get(&token_id).unwrap()
+    }
+}
+
+#[contract]
+struct ReentrantToken;
+
+#[contractimpl]
+impl ReentrantToken {
+    pub fn set_target(e: Env, lending: Address, loan_id: u32) {
+        e.storage().instance().set(&symbol_short!("target"), &(lending, loan_id));
+    }
+
+    pub fn transfer(e: Env, from: Address, _to: Address, _amount: i128) {
+        let Some((lending, loan_id)) =
+            e.storage().instance().get::<_, (Address, u32)>(&symbol_short!("target"))
+        else {
+            return;
+        };
+        LendingNFTClient::new(&e, &lending).liquidate_with_bonus(&loan_id, &from);
+    }
+}
+
+#[test]
+fn token_reentering_liquidation_is_rejected() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let liquidator = Address::generate(&e);
+    let token = e.register(ReentrantToken, ());
+    let address = e.register(LendingNFT, (&owner, 5000u32, i128::MAX, 0i128, 0u32, &token));
+    let client = LendingNFTClient::new(&e, &address);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    ReentrantTokenClient::new(&e, &token).set_target(&address, &loan_id);
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+
+    let reentry = soroban_sdk::Error::from_type_and_code(
+        xdr::ScErrorType::Context,
+        xdr::ScErrorCode::InvalidAction,
+    );
+    assert_eq!(client.try_liquidate_with_bonus(&loan_id, &liquidator), Err(Ok(reentry)));
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+    assert_eq!(client.owner_of(&1), address);
+}
+
+#[test]
+fn oracle_price_drives_collateral_value_and_health_factor() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    let prices = MockOracleClient::new(&e, &oracle);
+    prices.set_price(&1, &1000);
+    client.set_oracle(&owner, &oracle);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    assert_eq!(client.collateral_value(&1), 1000);
+    assert_eq!(client.health_factor(&loan_id), 10_000);
+
+    prices.set_price(&1, &2000);
+    assert_eq!(client.health_factor(&loan_id), 20_000);
+
+    prices.set_price(&1, &500);
+    assert_eq!(client.health_factor(&loan_id), 5000);
+}
+
+#[test]
+fn add_collateral_improves_health_factor() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    let prices = MockOracleClient::new(&e, &oracle);
+    prices.set_price(&1, &1000);
+    prices.set_price(&2, &1000);
+    client.set_oracle(&owner, &oracle);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    mint(&client, &owner, &borrower, 2);
+    assert_eq!(client.health_factor(&loan_id), 10_000);
+
+    client.add_collateral(&loan_id, &2, &borrower);
+    assert_eq!(client.health_factor(&loan_id), 20_000);
+    assert_eq!(client.owner_of(&2), client.address);
+    assert_eq!(client.collateral_loan_id(&2), Some(loan_id));
+    assert_eq!(client.get_loan_info(&loan_id).extra_collateral, vec![&e, 2]);
+
+    client.repay_loan(&loan_id, &500, &borrower);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(client.owner_of(&2), borrower);
+    assert_eq!(client.collateral_loan_id(&2), None);
+}
+
+#[test]
+fn collateral_deficit_is_value_missing_to_reach_max_ltv() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    let prices = MockOracleClient::new(&e, &oracle);
+    prices.set_price(&1, &1000);
+    client.set_oracle(&owner, &oracle);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    assert_eq!(client.collateral_deficit(&loan_id), 0);
+
+    prices.set_price(&1, &700);
+    assert_eq!(client.collateral_deficit(&loan_id), 300);
+
+    client.repay_loan(&loan_id, &100, &borrower);
+    assert_eq!(client.collateral_deficit(&loan_id), 100);
+
+    client.repay_loan(&loan_id, &400, &borrower);
+    assert_eq!(client.collateral_deficit(&loan_id), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #11)")]
+fn create_loan_fails_when_oracle_price_drops_below_max_ltv() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    let prices = MockOracleClient::new(&e, &oracle);
+    prices.set_price(&1, &1000);
+    client.set_oracle(&owner, &oracle);
+    mint(&client, &owner, &borrower, 1);
+
+    prices.set_price(&1, &999);
+    client.create_loan(&borrower, &1, &500, &500, &30, &borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #13)")]
+fn create_loan_fails_when_oracle_is_unavailable() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    client.set_oracle(&owner, &oracle);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 500);
+}
+
+#[test]
+fn zero_oracle_price_uses_configured_fallback() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    let prices = MockOracleClient::new(&e, &oracle);
+    prices.set_price(&1, &1000);
+    client.set_oracle(&owner, &oracle);
+    mint(&client, &owner, &borrower, 1);
+    assert_eq!(client.collateral_value(&1), 1000);
+
+    prices.set_price(&1, &0);
+    assert_eq!(client.price_fallback(), PriceFallback::Revert);
+    assert!(client.try_collateral_value(&1).is_err());
+
+    client.set_price_fallback(&owner, &PriceFallback::LastKnown);
+    assert_eq!(client.collateral_value(&1), 1000);
+
+    client.set_price_fallback(&owner, &PriceFallback::Static);
+    assert_eq!(client.collateral_value(&1), 1_000_000_000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #13)")]
+fn last_known_fallback_reverts_without_a_previous_price() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    MockOracleClient::new(&e, &oracle).set_price(&1, &0);
+    client.set_oracle(&owner, &oracle);
+    client.set_price_fallback(&owner, &PriceFallback::LastKnown);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 500);
+}
+
+/// Set up an overdue loan of 1000, which owes 1004 once its auction starts.
+fn overdue_loan<'a>(
+    e: &Env,
+    client: &LendingNFTClient,
+    owner: &Address,
+    borrower: &Address,
+) -> (u32, TokenClient<'a>) {
+    let token = client.payment_token();
+    let loan_id = mint_and_borrow(client, owner, borrower, 1, 1000);
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    (loan_id, TokenClient::new(e, &token))
+}
+
+fn funded_bidder(e: &Env, token: &TokenClient) -> Address {
+    let bidder = Address::generate(e);
+    StellarAssetClient::new(e, &token.address).mint(&bidder, &5000);
+    bidder
+}
+
+#[test]
+fn liquidation_auction_goes_to_highest_bidder() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_liquidation_penalty(&owner, &500);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let first = funded_bidder(&e, &token);
+    let second = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidating);
+    assert_eq!(client.auction(&loan_id).unwrap().debt, 1004);
+
+    client.bid(&loan_id, &first, &1500);
+    client.bid(&loan_id, &second, &2000);
+    assert_eq!(token.balance(&first), 5000);
+    assert_eq!(token.balance(&client.address), 2000);
+
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    assert_eq!(client.owner_of(&1), second);
+    assert_eq!(token.balance(&second), 3000);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS + 4);
+    assert_eq!(token.balance(&borrower), 1947);
+    assert_eq!(client.protocol_fees(), 49);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+    assert_eq!(client.get_loan_info(&loan_id).liquidator, Some(second));
+    assert!(!client.is_collateral(&1));
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #19)")]
+fn bid_fails_below_debt() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1003);
+}
+
+#[test]
+fn circuit_breaker_pauses_lending_on_rapid_liquidations() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_circuit_breaker_threshold(&owner, &2);
+    assert_eq!(client.circuit_breaker_threshold(), Some(2));
+    for token_id in 1..=3 {
+        mint_and_borrow(&client, &owner, &borrower, token_id, 1000);
+    }
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    for loan_id in 1..=3 {
+        client.start_liquidation(&loan_id);
+    }
+
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&1, &owner);
+    client.liquidate_loan(&2, &owner);
+    assert!(!client.lending_paused());
+    client.liquidate_loan(&3, &owner);
+    assert!(client.lending_paused());
+
+    client.reset_circuit_breaker(&owner);
+    assert!(!client.lending_paused());
+}
+
+#[test]
+fn circuit_breaker_counts_liquidations_with_bonus() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let token = TokenClient::new(&e, &client.payment_token());
+    let liquidator = funded_bidder(&e, &token);
+    client.set_circuit_breaker_threshold(&owner, &1);
+    for token_id in 1..=2 {
+        mint_and_borrow(&client, &owner, &borrower, token_id, 1000);
+        client.set_collateral_value(&owner, &token_id, &2000);
+    }
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+
+    client.liquidate_with_bonus(&1, &liquidator);
+    assert!(!client.lending_paused());
+    client.liquidate_with_bonus(&2, &liquidator);
+    assert!(client.lending_paused());
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #18)")]
+fn settle_liquidation_fails_while_auction_is_open() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1500);
+    client.settle_liquidation(&loan_id);
+}
+
+#[test]
+fn liquidate_loan_seizes_collateral_when_auction_gets_no_bids() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &owner);
+    assert_eq!(client.owner_of(&1), owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+}
+
+#[test]
+fn liquidate_loan_works_after_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &500, &1, &borrower);
+
+    e.ledger().set_timestamp(2 * day);
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(3 * day);
+    client.liquidate_loan(&loan_id, &owner);
+
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Liquidated);
+    assert_eq!(loan.liquidator, Some(owner.clone()));
+    assert_eq!(client.owner_of(&1), owner);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #16)")]
+fn liquidate_loan_fails_before_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.liquidate_loan(&loan_id, &owner);
+}
+
+#[test]
+fn surrender_collateral_closes_loan_before_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_grace_period(&owner, &(7 * 24 * 60 * 60));
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.owner_of(&1), client.address);
+
+    client.surrender_collateral(&loan_id, &borrower);
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Liquidated);
+    assert_eq!(loan.liquidator, Some(owner.clone()));
+    assert_eq!(loan.fees, 0);
+    assert_eq!(client.owner_of(&1), owner);
+    assert_eq!(client.collateral_loan_id(&1), None);
+    assert_eq!(client.total_outstanding(), 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #57)")]
+fn surrender_collateral_fails_for_pool_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = pool_loan(&e, &client, &owner, &borrower);
+
+    client.surrender_collateral(&loan_id, &borrower);
+}
+
+fn surrendered_loan(e: &Env, client: &LendingNFTClient, owner: &Address) -> u64 {
+    let borrower = Address::generate(e);
+    client.set_recollateralize_cooldown_days(owner, &2);
+    let loan_id = mint_and_borrow(client, owner, &borrower, 1, 1000);
+    e.ledger().set_timestamp(1000);
+    client.surrender_collateral(&loan_id, &borrower);
+    1000 + 2 * 24 * 60 * 60
+}
+
+#[test]
+fn create_loan_allows_token_once_cooldown_passed() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let cooldown_end = surrendered_loan(&e, &client, &owner);
+    assert_eq!(client.last_liquidated_at(&client.address, &1), Some(1000));
+
+    e.ledger().set_timestamp(cooldown_end);
+    let loan_id = client.create_loan(&owner, &1, &1000, &500, &30, &owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Active);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #52)")]
+fn create_loan_fails_within_cooldown_after_liquidation() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let cooldown_end = surrendered_loan(&e, &client, &owner);
+
+    e.ledger().set_timestamp(cooldown_end - 1);
+    client.create_loan(&owner, &1, &1000, &500, &30, &owner);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #6)")]
+fn surrender_collateral_fails_for_non_borrower() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.surrender_collateral(&loan_id, &owner);
+}
+
+#[test]
+fn repay_loan_succeeds_while_paused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.pause(&owner);
+
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #1000)")]
+fn liquidate_loan_fails_while_paused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.pause(&owner);
+
+    client.liquidate_loan(&loan_id, &owner);
+}
+
+#[test]
+fn pause_and_unpause_require_owner_auth() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let stranger = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let invoke = |fn_name| MockAuthInvoke {
+        contract: &client.address,
+        fn_name,
+        args: (&owner,).into_val(&e),
+        sub_invokes: &[],
+    };
+
+    let pause = invoke("pause");
+    let auths = [MockAuth { address: &stranger, invoke: &pause }];
+    assert!(client.mock_auths(&auths).try_pause(&owner).is_err());
+    assert!(!client.paused());
+
+    e.mock_all_auths();
+    client.pause(&owner);
+    let unpause = invoke("unpause");
+    let auths = [MockAuth { address: &stranger, invoke: &unpause }];
+    assert!(client.mock_auths(&auths).try_unpause(&owner).is_err());
+    assert!(client.paused());
+}
+
+#[test]
+fn registered_keeper_liquidates_for_lender() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let keeper = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+    client.add_keeper(&owner, &keeper);
+    assert!(client.is_keeper(&keeper));
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &keeper);
+    assert_eq!(client.owner_of(&1), owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn liquidate_loan_fails_for_removed_keeper() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let keeper = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+    client.add_keeper(&owner, &keeper);
+    client.remove_keeper(&owner, &keeper);
+    assert!(!client.is_keeper(&keeper));
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &keeper);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #21)")]
+fn liquidate_loan_fails_when_auction_has_bids() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1500);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &owner);
+}
+
+#[test]
+fn open_term_loan_is_liquidatable_only_after_call_window() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+    let fixed = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    mint(&client, &owner, &borrower, 2);
+    let open = client.create_open_term_loan(&borrower, &2, &1000, &500, &5);
+    assert_eq!(client.get_loan_info(&open).kind, LoanKind::OpenTerm);
+
+    e.ledger().set_timestamp(40 * day);
+    assert!(client.get_loan_summary(&fixed).liquidatable);
+    assert!(!client.get_loan_summary(&open).liquidatable);
+    assert_eq!(
+        client.try_start_liquidation(&open),
+        Err(Ok(soroban_sdk::Error::from_contract_error(15)))
+    );
+
+    client.call_loan(&open, &owner);
+    assert_eq!(client.get_loan_info(&open).called_at, Some(40 * day));
+    assert_eq!(client.loan_detail(&open).due_at, 45 * day);
+    e.ledger().set_timestamp(45 * day);
+    assert!(client.try_start_liquidation(&open).is_err());
+
+    e.ledger().set_timestamp(45 * day + 1);
+    client.start_liquidation(&open);
+    assert_eq!(client.get_loan_info(&open).status, LoanStatus::Liquidating);
+}
+
+#[test]
+fn uncalled_open_term_loan_owes_its_balance_without_schedule_or_discount() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_early_repay_discount(&owner, &5000);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_open_term_loan(&borrower, &1, &1_000_000, &3650, &5);
+
+    e.ledger().set_timestamp(10 * 24 * 60 * 60);
+    assert_eq!(client.outstanding_balance(&loan_id), 1_010_000);
+    assert_eq!(client.next_installment_due(&loan_id), (u64::MAX, 1_010_000));
+    assert_eq!(client.repayment_schedule(&loan_id), vec![&e, (u64::MAX, 1_010_000)]);
+}
+
+#[test]
+fn call_loan_rejects_fixed_term_and_repeat_calls() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let fixed = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    mint(&client, &owner, &borrower, 2);
+    let open = client.create_open_term_loan(&borrower, &2, &1000, &500, &5);
+    assert_eq!(
+        client.try_call_loan(&fixed, &owner),
+        Err(Ok(soroban_sdk::Error::from_contract_error(43)))
+    );
+    assert_eq!(
+        client.try_call_loan(&open, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(2)))
+    );
+
+    client.call_loan(&open, &owner);
+    assert_eq!(
+        client.try_call_loan(&open, &owner),
+        Err(Ok(soroban_sdk::Error::from_contract_error(44)))
+    );
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #15)")]
+fn start_liquidation_fails_before_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.start_liquidation(&loan_id);
+}
+
+#[test]
+fn available_capacity_tracks_outstanding_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    assert_eq!(client.available_capacity(), i128::MAX);
+    client.set_max_tvl(&owner, &2500);
+    assert_eq!(client.max_tvl(), Some(2500));
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.available_capacity(), 1500);
+
+    client.repay_loan(&loan_id, &400, &borrower);
+    assert_eq!(client.available_capacity(), 1900);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #22)")]
+fn create_loan_fails_above_available_capacity() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_max_tvl(&owner, &2500);
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    mint_and_borrow(&client, &owner, &borrower, 2, 1501);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #27)")]
+fn set_max_tvl_rejects_negative_values() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.set_max_tvl(&owner, &i128::MIN);
+}
+
+fn external_collection<'a>(
+    e: &Env,
+    client: &LendingNFTClient,
+    owner: &Address,
+    borrower: &Address,
+) -> LendingNFTClient<'a> {
+    let args = (owner, 5000u32, i128::MAX, 0i128, 0u32, client.payment_token());
+    let address = e.register(LendingNFT, args);
+    let collection = LendingNFTClient::new(e, &address);
+    collection.mint(borrower, &7, owner);
+    collection.approve(borrower, &client.address, &7, &1000);
+    client.set_external_collateral_value(owner, &collection.address, &7, &1_000_000);
+    collection
+}
+
+#[test]
+fn create_loan_with_collection_escrows_external_nft() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let collection = external_collection(&e, &client, &owner, &borrower);
+
+    client.add_collection(&owner, &collection.address);
+    assert_eq!(client.allowed_collections(), vec![&e, collection.address.clone()]);
+
+    let loan_id =
+        client.create_loan_with_collection(&borrower, &collection.address, &7, &1000, &500, &30);
+    assert_eq!(client.get_loan_info(&loan_id).collection, collection.address);
+    assert_eq!(collection.owner_of(&7), client.address);
+    assert!(!client.is_collateral(&7));
+
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert_eq!(collection.owner_of(&7), client.address);
+    client.reclaim_collateral(&loan_id, &7, &borrower);
+    assert_eq!(collection.owner_of(&7), borrower);
+}
+
+#[test]
+fn collateral_factor_scales_max_loan_per_collection() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let safe = external_collection(&e, &client, &owner, &borrower);
+    let risky = external_collection(&e, &client, &owner, &borrower);
+    client.add_collection(&owner, &safe.address);
+    client.add_collection(&owner, &risky.address);
+    assert_eq!(client.collateral_factor(&safe.address), 10_000);
+
+    client.set_collateral_factor(&owner, &safe.address, &8000);
+    client.set_collateral_factor(&owner, &risky.address, &4000);
+    assert_eq!(client.collateral_factor(&risky.address), 4000);
+    assert_eq!(client.max_borrowable_from_collection(&safe.address, &7), 400_000);
+    assert_eq!(client.max_borrowable_from_collection(&risky.address, &7), 200_000);
+
+    assert_eq!(
+        client.try_create_loan_with_collection(&borrower, &risky.address, &7, &300_000, &500, &30),
+        Err(Ok(soroban_sdk::Error::from_contract_error(11)))
+    );
+    client.create_loan_with_collection(&borrower, &safe.address, &7, &300_000, &500, &30);
+}
+
+#[test]
+fn collateral_factor_weighs_health_factor_and_deficit() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let collection = external_collection(&e, &client, &owner, &borrower);
+    client.add_collection(&owner, &collection.address);
+    client.set_collateral_factor(&owner, &collection.address, &5000);
+    client.set_oracle(&owner, &e.register(MockOracle, ()));
+    let loan_id =
+        client.create_loan_with_collection(&borrower, &collection.address, &7, &250_000, &500, &30);
+    assert_eq!(client.health_factor(&loan_id), 10_000);
+    assert_eq!(client.collateral_deficit(&loan_id), 0);
+
+    client.set_external_collateral_value(&owner, &collection.address, &7, &800_000);
+    assert_eq!(client.health_factor(&loan_id), 8000);
+    assert_eq!(client.collateral_deficit(&loan_id), 200_000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #9)")]
+fn collateral_factor_cannot_exceed_100_percent() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let collection = external_collection(&e, &client, &owner, &borrower);
+    client.set_collateral_factor(&owner, &collection.address, &10_001);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #23)")]
+fn create_loan_with_collection_fails_for_removed_collection() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let collection = external_collection(&e, &client, &owner, &borrower);
+
+    client.add_collection(&owner, &collection.address);
+    client.remove_collection(&owner, &collection.address);
+    assert_eq!(client.allowed_collections().len(), 0);
+
+    client.create_loan_with_collection(&borrower, &collection.address, &7, &1000, &500, &30);
+}
+
+#[test]
+fn collateral_value_is_rounded_to_valuation_granularity() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let oracle = e.register(MockOracle, ());
+    MockOracleClient::new(&e, &oracle).set_price(&1, &123_456_789);
+    client.set_oracle(&owner, &oracle);
+    assert_eq!(client.collateral_value(&1), 123_456_789);
+
+    client.set_valuation_granularity(&owner, &1000);
+    assert_eq!(client.valuation_granularity(), 1000);
+    assert_eq!(client.collateral_value(&1), 123_456_000);
+    assert_eq!(client.max_borrowable(&1), 61_728_000);
+}
+
+#[test]
+fn total_fees_charged_accumulates_across_operations() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_origination_fee(&owner, &100);
+    client.set_liquidation_penalty(&owner, &500);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+    assert_eq!(client.loan_detail(&loan_id).total_fees_charged, 10);
+
+    client.start_liquidation(&loan_id);
+    assert_eq!(client.auction(&loan_id).unwrap().debt, 1014);
+    client.bid(&loan_id, &bidder, &2000);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    assert_eq!(client.loan_detail(&loan_id).total_fees_charged, 59);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #24)")]
+fn transfer_fails_for_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.transfer(&borrower, &other, &1);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #24)")]
+fn burn_fails_for_collateral() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.burn(&borrower, &1);
+}
+
+#[test]
+fn version_and_features_describe_deployment() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    assert_eq!(client.version(), client.schema_version());
+    assert_eq!(client.features(), vec![&e, symbol_short!("auctions")]);
+
+    client.set_oracle(&owner, &e.register(MockOracle, ()));
+    client.set_rate_model(&owner, &RateModel { base_rate: 500, max_rate: 2500 });
+    assert_eq!(
+        client.features(),
+        vec![&e, symbol_short!("auctions"), symbol_short!("oracle"), symbol_short!("pools")]
+    );
+}
+
+#[test]
+fn post_upgrade_migrate_moves_legacy_loan_once() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    fund(&e, &client, &borrower, 800);
+    assert_eq!(client.schema_version(), 1);
+    assert!(!client.post_upgrade_migrate(&owner, &0));
+
+    client.mint(&borrower, &1, &owner);
+    e.as_contract(&client.address, || {
+        let storage = e.storage().instance();
+        storage.remove(&symbol_short!("version"));
+        storage.set(&symbol_short!("borrower"), &borrower);
+        storage.set(&symbol_short!("amount"), &1000i128);
+        storage.set(&symbol_short!("rate"), &500u32);
+        storage.set(&symbol_short!("duration"), &30u32);
+        storage.set(&symbol_short!("created"), &0u64);
+        storage.set(&symbol_short!("status"), &0u32);
+        storage.set(&symbol_short!("repaid"), &200i128);
+        storage.set(&symbol_short!("collat"), &1u32);
+        storage.set(&symbol_short!("next_id"), &2u32);
+    });
+    assert_eq!(client.schema_version(), 0);
+
+    assert!(client.post_upgrade_migrate(&owner, &0));
+    assert!(!client.post_upgrade_migrate(&owner, &0));
+    assert_eq!(client.schema_version(), 1);
+    assert!(!e.as_contract(&client.address, || {
+        e.storage().instance().has(&symbol_short!("borrower"))
+    }));
+
+    let loan = client.get_loan_info(&1);
+    assert_eq!(loan.borrower, borrower);
+    assert_eq!(loan.amount, 1000);
+    assert_eq!(loan.status, LoanStatus::Active);
+    assert!(client.is_collateral(&1));
+    assert_eq!(client.outstanding_balance(&1), 800);
+
+    client.repay_loan(&1, &800, &borrower);
+    assert_eq!(client.get_loan_info(&1).status, LoanStatus::Repaid);
+    assert_eq!(client.owner_of(&1), borrower);
+    assert!(client.try_reclaim_collateral(&1, &1, &borrower).is_err());
+}
+
+#[test]
+fn liquidate_loan_only_succeeds_once() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &owner);
+    assert_eq!(
+        client.try_liquidate_loan(&loan_id, &owner),
+        Err(Ok(soroban_sdk::Error::from_contract_error(29)))
+    );
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+    assert_eq!(client.owner_of(&1), owner);
+    assert_eq!(client.balance(&owner), 1);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #29)")]
+fn settle_liquidation_fails_for_liquidated_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1500);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    client.settle_liquidation(&loan_id);
+}
+
+fn liquidate_with_dust_threshold(threshold: i128) -> (i128, i128) {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_dust_threshold(&owner, &threshold);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &2000);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    (token.balance(&borrower), client.protocol_fees())
+}
+
+#[test]
+fn liquidation_surplus_above_dust_is_returned() {
+    assert_eq!(liquidate_with_dust_threshold(500), (1996, 0));
+}
+
+#[test]
+fn liquidation_surplus_below_dust_is_swept() {
+    assert_eq!(liquidate_with_dust_threshold(1000), (1000, 996));
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #26)")]
+fn max_borrowable_fails_on_overflowing_collateral_value() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.mint(&borrower, &1, &owner);
+    client.set_collateral_value(&owner, &1, &i128::MAX);
+    client.max_borrowable(&1);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #26)")]
+fn calculate_interest_fails_on_overflowing_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let value = i128::MAX / 10_000;
+    fund(&e, &client, &owner, value / 2);
+
+    client.mint(&borrower, &1, &owner);
+    client.set_collateral_value(&owner, &1, &value);
+    let loan_id = client.create_loan(&borrower, &1, &(value / 2), &500, &30, &borrower);
+
+    e.ledger().set_timestamp(24 * 60 * 60);
+    client.calculate_interest(&loan_id);
+}
+
+#[test]
+fn repay_loan_applies_only_outstanding_balance() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &1, &borrower);
+    let repayment = client.repay_loan(&loan_id, &1998, &borrower);
+    assert_eq!(repayment, Repayment { fees: 0, interest: 0, principal: 999 });
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Repaid);
+    assert_eq!(loan.repaid, 1000);
+    assert_eq!(client.get_payments(&loan_id), vec![&e, (0u64, 1i128), (0, 999)]);
+
+    let other = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    client.repay_loan(&other, &i128::MAX, &borrower);
+    assert_eq!(client.get_loan_info(&other).repaid, 1000);
+}
+
+#[test]
+fn has_liquidatable_loan_detects_overdue_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    e.ledger().set_timestamp(20 * 24 * 60 * 60);
+    mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    assert!(!client.has_liquidatable_loan(&borrower));
+
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+    assert!(client.has_liquidatable_loan(&borrower));
+    assert!(!client.has_liquidatable_loan(&owner));
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #27)")]
+fn create_loan_fails_for_zero_amount() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 0);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #27)")]
+fn create_loan_fails_for_negative_amount() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, -100);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #27)")]
+fn repay_loan_fails_for_zero_amount() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &0, &borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #27)")]
+fn repay_loan_fails_for_negative_amount() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    client.repay_loan(&loan_id, &-100, &borrower);
+}
+
+#[test]
+fn lending_pause_only_blocks_new_loans() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    mint(&client, &owner, &borrower, 2);
+    client.pause_lending(&owner);
+    assert!(client.lending_paused());
+    assert!(!client.paused());
+
+    client.transfer(&borrower, &other, &2);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    assert!(client.try_create_loan(&other, &2, &1000, &500, &30, &other).is_err());
+
+    client.unpause_lending(&owner);
+    client.create_loan(&other, &2, &1000, &500, &30, &other);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #28)")]
+fn create_loan_fails_while_lending_is_paused() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    client.pause_lending(&owner);
+    mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #1000)")]
+fn global_pause_blocks_transfers_independently_of_lending_pause() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+
+    mint(&client, &owner, &borrower, 1);
+    client.pause(&owner);
+    assert!(!client.lending_paused());
+    client.transfer(&borrower, &other, &1);
+}
+
+#[test]
+fn interest_skips_frozen_periods_when_enabled() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_freeze_pauses_interest(&owner, &true);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &365, &borrower);
+
+    e.ledger().set_timestamp(100 * 24 * 60 * 60);
+    client.pause(&owner);
+    e.ledger().set_timestamp(150 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 27);
+
+    e.ledger().set_timestamp(200 * 24 * 60 * 60);
+    client.unpause(&owner);
+    e.ledger().set_timestamp(365 * 24 * 60 * 60);
+    assert_eq!(client.calculate_interest(&loan_id), 72);
+
+    client.set_freeze_pauses_interest(&owner, &false);
+    assert_eq!(client.calculate_interest(&loan_id), 100);
+}
+
+#[test]
+fn total_outstanding_tracks_open_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (liquidated_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+    let repaid_id = mint_and_borrow(&client, &owner, &borrower, 2, 500);
+    assert_eq!(client.total_outstanding(), 1500);
+
+    client.repay_loan(&repaid_id, &200, &borrower);
+    assert_eq!(client.total_outstanding(), 1300);
+
+    client.repay_loan(&repaid_id, &300, &borrower);
+    assert_eq!(client.get_loan_info(&repaid_id).status, LoanStatus::Repaid);
+    assert_eq!(client.total_outstanding(), 1000);
+
+    client.start_liquidation(&liquidated_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&liquidated_id, &owner);
+    assert_eq!(client.total_outstanding(), 0);
+}
+
+#[test]
+fn collection_stats_are_tracked_per_collection() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let collection = external_collection(&e, &client, &owner, &borrower);
+    client.add_collection(&owner, &collection.address);
+    client.set_external_collateral_value(&owner, &collection.address, &7, &1000);
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+    client.set_collateral_value(&owner, &1, &4000);
+    client.set_collateral_value(&owner, &2, &4000);
+
+    let repaid_id = client.create_loan(&borrower, &1, &1000, &500, &30, &borrower);
+    client.create_loan(&borrower, &2, &2000, &500, &30, &borrower);
+    client.create_loan_with_collection(&borrower, &collection.address, &7, &500, &500, &30);
+
+    let native = CollectionStats {
+        active_loans: 2,
+        tvl: 3000,
+        collateral_value: 8000,
+        average_ltv_bps: 3750,
+    };
+    assert_eq!(client.collection_stats(&client.address), native);
+    let external = CollectionStats {
+        active_loans: 1,
+        tvl: 500,
+        collateral_value: 1000,
+        average_ltv_bps: 5000,
+    };
+    assert_eq!(client.collection_stats(&collection.address), external);
+
+    client.repay_loan(&repaid_id, &1000, &borrower);
+    let native = CollectionStats {
+        active_loans: 1,
+        tvl: 2000,
+        collateral_value: 4000,
+        average_ltv_bps: 5000,
+    };
+    assert_eq!(client.collection_stats(&client.address), native);
+    assert_eq!(client.collection_stats(&collection.address), external);
+}
+
+#[test]
+fn refinance_loan_capitalizes_accrued_interest() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_grace_period(&owner, &(2 * 24 * 60 * 60));
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let expiry = 31 * 24 * 60 * 60;
+    e.ledger().set_timestamp(expiry);
+    assert_eq!(client.calculate_interest(&loan_id), 4);
+
+    client.refinance_loan(&loan_id, &60, &800, &borrower);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), Symbol::new(&e, "refinanced"), loan_id, 2u32).into_val(&e),
+                client.get_loan_info(&loan_id).into_val(&e)
+            )
+        ]
+    );
+
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.amount, 1004);
+    assert_eq!(loan.interest_rate, 800);
+    assert_eq!(loan.duration_days, 60);
+    assert_eq!(loan.created_at, expiry);
+    assert_eq!(client.outstanding_balance(&loan_id), 1004);
+    assert_eq!(client.total_outstanding(), 1004);
+    assert!(client.is_collateral(&1));
+}
+
+#[test]
+fn refinance_loan_checks_loan_limits_on_new_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_grace_period(&owner, &(2 * 24 * 60 * 60));
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+
+    client.set_max_tvl(&owner, &1003);
+    assert_eq!(
+        client.try_refinance_loan(&loan_id, &60, &800, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(22)))
+    );
+    client.pause_lending(&owner);
+    assert_eq!(
+        client.try_refinance_loan(&loan_id, &60, &800, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(28)))
+    );
+    client.unpause_lending(&owner);
+    client.set_max_tvl(&owner, &1004);
+    client.refinance_loan(&loan_id, &60, &800, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 1004);
+}
+
+#[test]
+fn refinance_loan_resolves_default_rate() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(
+        client.try_refinance_loan(&loan_id, &60, &USE_DEFAULT_RATE, &borrower),
+        Err(Ok(soroban_sdk::Error::from_contract_error(41)))
+    );
+
+    client.set_default_interest_rate(&owner, &700);
+    client.refinance_loan(&loan_id, &60, &USE_DEFAULT_RATE, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).interest_rate, 700);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #56)")]
+fn refinance_loan_fails_past_grace_period() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+    client.set_grace_period(&owner, &day);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    e.ledger().set_timestamp(31 * day);
+    client.refinance_loan(&loan_id, &60, &800, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).duration_days, 60);
+
+    e.ledger().set_timestamp(92 * day + 1);
+    client.refinance_loan(&loan_id, &60, &800, &borrower);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #6)")]
+fn refinance_loan_requires_borrower() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.refinance_loan(&loan_id, &60, &800, &owner);
+}
+
+#[test]
+fn installment_loan_repaid_on_schedule() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    fund(&e, &client, &borrower, 30);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_installment_loan(&borrower, &1, &3650, &1000, &30, &3);
+    let day = 24 * 60 * 60;
+    assert_eq!(client.next_installment_due(&loan_id), (10 * day, 1226));
+
+    e.ledger().set_timestamp(10 * day);
+    client.repay_loan(&loan_id, &1226, &borrower);
+    assert_eq!(client.next_installment_due(&loan_id), (20 * day, 1227));
+
+    e.ledger().set_timestamp(20 * day);
+    client.repay_loan(&loan_id, &1227, &borrower);
+    assert_eq!(client.next_installment_due(&loan_id), (30 * day, 1227));
+    assert!(client.try_mark_defaulted(&loan_id, &owner).is_err());
+
+    e.ledger().set_timestamp(30 * day);
+    client.repay_loan(&loan_id, &1227, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Repaid);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(client.owner_of(&1), borrower);
+}
+
+#[test]
+fn missed_installment_lets_lender_mark_loan_defaulted() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_installment_loan(&borrower, &1, &3650, &1000, &30, &3);
+    e.ledger().set_timestamp(10 * 24 * 60 * 60);
+    client.repay_loan(&loan_id, &1000, &borrower);
+    assert!(client.try_mark_defaulted(&loan_id, &owner).is_err());
+
+    e.ledger().set_timestamp(10 * 24 * 60 * 60 + 1);
+    client.mark_defaulted(&loan_id, &owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Defaulted);
+    assert_eq!(client.get_loans_by_status(&LoanStatus::Defaulted, &0, &10), vec![&e, loan_id]);
+    assert_eq!(client.health_factor(&loan_id), 0);
+    assert!(client.has_liquidatable_loan(&borrower));
+}
+
+#[test]
+fn interest_only_loan_owes_principal_after_window() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_interest_only_loan(&borrower, &1, &1000, &3650, &30, &10);
+    let day = 24 * 60 * 60;
+    assert_eq!(client.get_loan_info(&loan_id).interest_only_days, 10);
+
+    e.ledger().set_timestamp(10 * day - 1);
+    assert_eq!(client.outstanding_balance(&loan_id), 9);
+    assert_eq!(client.calculate_interest(&loan_id), 9);
+
+    e.ledger().set_timestamp(10 * day);
+    assert_eq!(client.outstanding_balance(&loan_id), 1010);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #54)")]
+fn interest_only_period_cannot_outlast_loan() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    client.create_interest_only_loan(&borrower, &1, &1000, &3650, &30, &31);
+}
+
+#[test]
+fn days_remaining_crosses_zero_at_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    fund(&e, &client, &borrower, 1000);
+    let day = 24 * 60 * 60;
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.days_remaining(&loan_id), 30);
+
+    e.ledger().set_timestamp(29 * day);
+    assert_eq!(client.days_remaining(&loan_id), 1);
+    e.ledger().set_timestamp(30 * day);
+    assert_eq!(client.days_remaining(&loan_id), 0);
+    e.ledger().set_timestamp(30 * day + 1);
+    assert_eq!(client.days_remaining(&loan_id), -1);
+    e.ledger().set_timestamp(35 * day);
+    assert_eq!(client.days_remaining(&loan_id), -5);
+
+    client.repay_loan(&loan_id, &client.outstanding_balance(&loan_id), &borrower);
+    assert_eq!(client.days_remaining(&loan_id), 0);
+}
+
+#[test]
+fn can_liquidate_checks_status_grace_and_caller() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let keeper = Address::generate(&e);
+    let stranger = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+    client.set_grace_period(&owner, &day);
+    client.add_keeper(&owner, &keeper);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let repaid = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    client.repay_loan(&repaid, &1000, &borrower);
+
+    e.ledger().set_timestamp(31 * day);
+    assert!(!client.can_liquidate(&loan_id, &stranger));
+    assert!(!client.can_liquidate(&repaid, &stranger));
+
+    e.ledger().set_timestamp(31 * day + 1);
+    assert!(client.can_liquidate(&loan_id, &stranger));
+    client.pause(&owner);
+    assert!(!client.can_liquidate(&loan_id, &stranger));
+    client.unpause(&owner);
+
+    client.start_liquidation(&loan_id);
+    assert!(!client.can_liquidate(&loan_id, &owner));
+    e.ledger().set_timestamp(32 * day + 1);
+    assert!(!client.can_liquidate(&loan_id, &stranger));
+    assert!(client.can_liquidate(&loan_id, &keeper));
+    assert!(client.can_liquidate(&loan_id, &owner));
+    client.liquidate_loan(&loan_id, &keeper);
+    assert!(!client.can_liquidate(&loan_id, &owner));
+}
+
+#[test]
+fn can_liquidate_is_false_once_auction_has_bids() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &funded_bidder(&e, &token), &2000);
+
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    assert!(!client.can_liquidate(&loan_id, &owner));
+}
+
+#[test]
+fn liquidatable_at_includes_grace_period() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+    client.set_grace_period(&owner, &(2 * day));
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let at = client.liquidatable_at(&loan_id);
+    assert_eq!(at, 32 * day + 1);
+
+    e.ledger().set_timestamp(at - 1);
+    assert_eq!(
+        client.try_start_liquidation(&loan_id),
+        Err(Ok(soroban_sdk::Error::from_contract_error(15)))
+    );
+
+    e.ledger().set_timestamp(at);
+    client.start_liquidation(&loan_id);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidating);
+    assert_eq!(client.liquidatable_at(&loan_id), u64::MAX);
+}
+
+#[test]
+fn overdue_loan_defaults_after_grace_period_then_liquidates() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let day = 24 * 60 * 60;
+    client.set_grace_period(&owner, &(2 * day));
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    e.ledger().set_timestamp(32 * day);
+    assert!(client.try_mark_defaulted(&loan_id, &owner).is_err());
+
+    e.ledger().set_timestamp(32 * day + 1);
+    client.mark_defaulted(&loan_id, &owner);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), symbol_short!("defaulted"), loan_id, 2u32).into_val(&e),
+                client.get_loan_info(&loan_id).into_val(&e)
+            )
+        ]
+    );
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Defaulted);
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(33 * day + 1);
+    client.liquidate_loan(&loan_id, &owner);
+    assert_eq!(client.get_loan_info(&loan_id).status, LoanStatus::Liquidated);
+    assert_eq!(client.owner_of(&1), owner);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #2)")]
+fn mark_defaulted_requires_lender() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    e.ledger().set_timestamp(31 * 24 * 60 * 60);
+
+    client.mark_defaulted(&loan_id, &borrower);
+}
+
+#[test]
+fn weighted_avg_rate_weighs_rates_by_outstanding_principal() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    assert_eq!(client.weighted_avg_rate(), 0);
+    mint(&client, &owner, &borrower, 1);
+    mint(&client, &owner, &borrower, 2);
+
+    let small = client.create_loan(&borrower, &1, &1000, &500, &30, &borrower);
+    client.create_loan(&borrower, &2, &3000, &1000, &30, &borrower);
+    assert_eq!(client.weighted_avg_rate(), 875);
+
+    client.repay_loan(&small, &1000, &borrower);
+    assert_eq!(client.weighted_avg_rate(), 1000);
+}
+
+#[test]
+fn rate_discount_lowers_borrower_interest_rate() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let other = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_rate_discount(&owner, &borrower, &150);
+    assert_eq!(client.rate_discount_bps_of(&borrower), 150);
+
+    let discounted = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let full = mint_and_borrow(&client, &owner, &other, 2, 1000);
+    assert_eq!(client.get_loan_info(&discounted).interest_rate, 350);
+    assert_eq!(client.get_loan_info(&full).interest_rate, 500);
+    assert_eq!(client.original_terms(&discounted).interest_rate, 350);
+}
+
+#[test]
+fn assigned_lender_receives_liquidation_proceeds() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let buyer = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.assign_lender(&loan_id, &buyer, &owner);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), Symbol::new(&e, "lender_assigned"), loan_id, 2u32)
+                    .into_val(&e),
+                client.get_loan_info(&loan_id).into_val(&e)
+            )
+        ]
+    );
+    assert_eq!(client.get_loan_info(&loan_id).lender, buyer);
+    assert!(client.try_assign_lender(&loan_id, &owner, &owner).is_err());
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1004);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    assert_eq!(token.balance(&buyer), 1004);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS - 1000);
+}
+
+#[test]
+fn create_loan_allows_principal_up_to_per_nft_cap() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    StellarAssetClient::new(&e, &token).mint(&owner, &1000);
+    let address = e.register(LendingNFT, (&owner, 5000u32, 1000i128, 0i128, 0u32, token));
+    let client = LendingNFTClient::new(&e, &address);
+    assert_eq!(client.max_loan_per_nft(), 1000);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 1000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #31)")]
+fn create_loan_fails_above_per_nft_cap() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    let address = e.register(LendingNFT, (&owner, 5000u32, 1000i128, 0i128, 0u32, token));
+    let client = LendingNFTClient::new(&e, &address);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 1001);
+}
+
+#[test]
+fn create_loan_allows_principal_at_minimum() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    StellarAssetClient::new(&e, &token).mint(&owner, &100);
+    let address = e.register(LendingNFT, (&owner, 5000u32, i128::MAX, 100i128, 0u32, token));
+    let client = LendingNFTClient::new(&e, &address);
+    assert_eq!(client.min_loan_amount(), 100);
+
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 100);
+    assert_eq!(client.get_loan_info(&loan_id).amount, 100);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #50)")]
+fn create_loan_fails_below_minimum() {
+    let e = Env::default();
+    e.mock_all_auths();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let token = e.register_stellar_asset_contract_v2(owner.clone()).address();
+    let address = e.register(LendingNFT, (&owner, 5000u32, i128::MAX, 100i128, 0u32, token));
+    let client = LendingNFTClient::new(&e, &address);
+
+    mint_and_borrow(&client, &owner, &borrower, 1, 99);
+}
+
+#[test]
+fn repayment_schedule_sums_to_total_owed() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_origination_fee(&owner, &100);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_installment_loan(&borrower, &1, &3650, &1000, &30, &3);
+    let day = 24 * 60 * 60;
+
+    let schedule = client.repayment_schedule(&loan_id);
+    assert_eq!(schedule, vec![&e, (10 * day, 1238), (20 * day, 1239), (30 * day, 1239)]);
+    let total: i128 = schedule.iter().map(|(_, amount)| amount).sum();
+    assert_eq!(total, 3650 + 30 + 36);
+
+    let unscheduled = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    assert_eq!(client.repayment_schedule(&unscheduled), vec![&e, (30 * day, 1014)]);
+}
+
+#[test]
+fn liquidate_loan_records_liquidator() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, _) = overdue_loan(&e, &client, &owner, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).liquidator, None);
+
+    client.start_liquidation(&loan_id);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.liquidate_loan(&loan_id, &owner);
+    assert_eq!(client.get_loan_info(&loan_id).liquidator, Some(owner));
+}
+
+#[test]
+fn longest_duration_does_not_overflow_liquidation_timing() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_loan(&borrower, &1, &1000, &500, &u32::MAX, &borrower);
+    let due_at = u32::MAX as u64 * 24 * 60 * 60;
+    assert_eq!(client.loan_detail(&loan_id).due_at, due_at);
+
+    e.ledger().set_timestamp(due_at);
+    assert!(!client.has_liquidatable_loan(&borrower));
+    e.ledger().set_timestamp(due_at + 1);
+    assert!(client.has_liquidatable_loan(&borrower));
+    client.start_liquidation(&loan_id);
+    assert_eq!(client.auction(&loan_id).unwrap().ends_at, due_at + 1 + 24 * 60 * 60);
+}
+
+#[test]
+fn many_installments_over_longest_duration_do_not_overflow() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    let loan_id = client.create_installment_loan(&borrower, &1, &1000, &500, &u32::MAX, &100_000);
+
+    let (due, _) = client.next_installment_due(&loan_id);
+    assert_eq!(due, u32::MAX as u64 * 24 * 60 * 60 / 100_000);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #32)")]
+fn create_loan_fails_once_total_loan_cap_is_reached() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_max_total_loans(&owner, &2);
+    assert_eq!(client.max_total_loans(), Some(2));
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    client.repay_loan(&first, &500, &borrower);
+    mint_and_borrow(&client, &owner, &borrower, 2, 500);
+
+    mint_and_borrow(&client, &owner, &borrower, 3, 500);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #36)")]
+fn create_loan_fails_once_borrower_holds_max_open_loans() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_max_loans_per_user(&owner, &2);
+    assert_eq!(client.max_loans_per_user(), Some(2));
+    let first = mint_and_borrow(&client, &owner, &borrower, 1, 500);
+    mint_and_borrow(&client, &owner, &borrower, 2, 500);
+    client.repay_loan(&first, &500, &borrower);
+    mint_and_borrow(&client, &owner, &borrower, 3, 500);
+    mint_and_borrow(&client, &owner, &Address::generate(&e), 4, 500);
+
+    mint_and_borrow(&client, &owner, &borrower, 5, 500);
+}
+
+#[test]
+fn create_loan_allows_duration_at_max() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_max_duration_days(&owner, &30);
+    assert_eq!(client.max_duration_days(), Some(30));
+    mint(&client, &owner, &borrower, 1);
+
+    let loan_id = client.create_loan(&borrower, &1, &500, &500, &30, &borrower);
+    assert_eq!(client.get_loan_info(&loan_id).duration_days, 30);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #45)")]
+fn create_loan_fails_above_max_duration() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_max_duration_days(&owner, &30);
+    mint(&client, &owner, &borrower, 1);
+
+    client.create_loan(&borrower, &1, &500, &500, &31, &borrower);
+}
+
+#[test]
+fn liquidate_with_bonus_splits_price_between_lender_and_borrower() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_liquidation_bonus(&owner, &1000);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    client.set_collateral_value(&owner, &1, &2000);
+    let liquidator = funded_bidder(&e, &token);
+
+    client.liquidate_with_bonus(&loan_id, &liquidator);
+    assert_eq!(token.balance(&liquidator), 3200);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS + 4);
+    assert_eq!(token.balance(&borrower), 1796);
+    assert_eq!(token.balance(&client.address), 0);
+    assert_eq!(client.owner_of(&1), liquidator);
+    let loan = client.get_loan_info(&loan_id);
+    assert_eq!(loan.status, LoanStatus::Liquidated);
+    assert_eq!(loan.liquidator, Some(liquidator));
+}
+
+#[test]
+fn liquidate_with_bonus_pays_lender_whole_price_below_debt() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_liquidation_bonus(&owner, &1000);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    client.set_collateral_value(&owner, &1, &1000);
+    let liquidator = funded_bidder(&e, &token);
+
+    client.liquidate_with_bonus(&loan_id, &liquidator);
+    assert_eq!(token.balance(&liquidator), 4100);
+    assert_eq!(token.balance(&owner), LENDER_FUNDS - 100);
+    assert_eq!(token.balance(&borrower), 1000);
+    assert_eq!(client.owner_of(&1), liquidator);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #15)")]
+fn liquidate_with_bonus_fails_before_due_date() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+
+    client.liquidate_with_bonus(&loan_id, &owner);
+}
+
+#[test]
+fn loan_detail_exposes_liquidator_once_liquidated() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+    assert_eq!(client.loan_detail(&loan_id).liquidator, None);
+
+    client.start_liquidation(&loan_id);
+    client.bid(&loan_id, &bidder, &1500);
+    assert_eq!(client.loan_detail(&loan_id).liquidator, None);
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    assert_eq!(client.loan_detail(&loan_id).liquidator, Some(bidder));
+}
+
+#[test]
+fn create_loan_with_max_rate_accepts_quoted_rate() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_rate_discount(&owner, &borrower, &150);
+    mint(&client, &owner, &borrower, 1);
+
+    let loan_id = client.create_loan_with_max_rate(&borrower, &1, &1000, &500, &30, &350);
+    assert_eq!(client.get_loan_info(&loan_id).interest_rate, 350);
+}
+
+#[test]
+#[should_panic(expected = "Error(Contract, #35)")]
+fn create_loan_with_max_rate_rejects_rate_bump() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_rate_discount(&owner, &borrower, &150);
+    mint(&client, &owner, &borrower, 1);
+
+    client.set_rate_discount(&owner, &borrower, &0);
+    client.create_loan_with_max_rate(&borrower, &1, &1000, &500, &30, &350);
+}
+
+#[test]
+fn create_loan_with_guards_checks_default_rate_and_deadline() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    client.set_default_interest_rate(&owner, &500);
+    mint(&client, &owner, &borrower, 1);
+    let guards = LoanGuards { max_acceptable_rate: Some(500), deadline: 100 };
+
+    client.set_default_interest_rate(&owner, &600);
+    assert_eq!(
+        client.try_create_loan_with_guards(&borrower, &1, &1000, &USE_DEFAULT_RATE, &30, &guards),
+        Err(Ok(soroban_sdk::Error::from_contract_error(35)))
+    );
+
+    client.set_default_interest_rate(&owner, &500);
+    e.ledger().set_timestamp(101);
+    assert_eq!(
+        client.try_create_loan_with_guards(&borrower, &1, &1000, &USE_DEFAULT_RATE, &30, &guards),
+        Err(Ok(soroban_sdk::Error::from_contract_error(42)))
+    );
+
+    e.ledger().set_timestamp(100);
+    let loan_id =
+        client.create_loan_with_guards(&borrower, &1, &1000, &USE_DEFAULT_RATE, &30, &guards);
+    assert_eq!(client.get_loan_info(&loan_id).interest_rate, 500);
+}
+
+#[test]
+fn create_loan_with_guards_allows_any_rate_without_max() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    mint(&client, &owner, &borrower, 1);
+    let guards = LoanGuards { max_acceptable_rate: None, deadline: u64::MAX };
+
+    let loan_id = client.create_loan_with_guards(&borrower, &1, &1000, &9000, &30, &guards);
+    assert_eq!(client.get_loan_info(&loan_id).interest_rate, 9000);
+}
+
+#[test]
+fn action_allowed_follows_operational_flags() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let actions = [
+        symbol_short!("borrow"),
+        symbol_short!("repay"),
+        symbol_short!("liquidate"),
+        symbol_short!("transfer"),
+    ];
+    let allowed = |client: &LendingNFTClient| actions.clone().map(|a| client.action_allowed(&a));
+    assert_eq!(allowed(&client), [true, true, true, true]);
+    assert!(!client.action_allowed(&symbol_short!("unknown")));
+
+    client.pause_lending(&owner);
+    assert_eq!(allowed(&client), [false, true, true, true]);
+
+    client.pause(&owner);
+    assert_eq!(allowed(&client), [false, true, false, false]);
+
+    client.unpause_lending(&owner);
+    assert_eq!(allowed(&client), [true, true, false, false]);
+}
+
+fn contract_events(e: &Env, client: &LendingNFTClient) -> Vec<(Address, Vec<Val>, Val)> {
+    let mut events = Vec::new(e);
+    for event in e.events().all().iter() {
+        if event.0 == client.address {
+            events.push_back(event);
+        }
+    }
+    events
+}
+
+#[test]
+fn loan_lifecycle_events_carry_loan_id_and_nonce_topics() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (symbol_short!("loan"), symbol_short!("created"), loan_id, 1u32).into_val(&e),
+            client.get_loan_info(&loan_id).into_val(&e)
+        )
+    );
+
+    client.repay_loan(&loan_id, &400, &borrower);
+    let partial = Repayment { fees: 0, interest: 0, principal: 400 };
+    assert_eq!(
+        contract_events(&e, &client),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), symbol_short!("repayment"), loan_id, 2u32).into_val(&e),
+                partial.into_val(&e)
+            )
+        ]
+    );
+
+    client.repay_loan(&loan_id, &600, &borrower);
+    let last = Repayment { fees: 0, interest: 0, principal: 600 };
+    assert_eq!(
+        contract_events(&e, &client),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), symbol_short!("repayment"), loan_id, 3u32).into_val(&e),
+                last.into_val(&e)
+            ),
+            (
+                client.address.clone(),
+                (symbol_short!("loan"), symbol_short!("repaid"), loan_id, 4u32).into_val(&e),
+                client.get_loan_info(&loan_id).into_val(&e)
+            )
+        ]
+    );
+}
+
+#[test]
+fn liquidation_events_carry_loan_id_and_nonce_topics() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let (loan_id, token) = overdue_loan(&e, &client, &owner, &borrower);
+    let bidder = funded_bidder(&e, &token);
+
+    client.start_liquidation(&loan_id);
+    assert_eq!(
+        e.events().all(),
+        vec![
+            &e,
+            (
+                client.address.clone(),
+                (symbol_short!("auction"), symbol_short!("started"), loan_id, 2u32).into_val(&e),
+                client.auction(&loan_id).unwrap().into_val(&e)
+            )
+        ]
+    );
+
+    client.bid(&loan_id, &bidder, &1500);
+    assert_eq!(
+        e.events().all().last().unwrap(),
+        (
+            client.address.clone(),
+            (symbol_short!("auction"), symbol_short!("bid"), loan_id, 3u32).into_val(&e),
+            client.auction(&loan_id).unwrap().into_val(&e)
+        )
+    );
+
+    e.ledger().set_timestamp(32 * 24 * 60 * 60);
+    client.settle_liquidation(&loan_id);
+    let liquidated = (
+        client.address.clone(),
+        (symbol_short!("loan"), Symbol::new(&e, "liquidated"), loan_id, 4u32).into_val(&e),
+        client.get_loan_info(&loan_id).into_val(&e),
+    );
+    assert!(e.events().all().contains(&liquidated));
+}
+
+#[test]
+fn action_nonce_increments_with_each_loan_event() {
+    let e = Env::default();
+    let owner = Address::generate(&e);
+    let borrower = Address::generate(&e);
+    let client = create_client(&e, &owner);
+    let loan_id = mint_and_borrow(&client, &owner, &borrower, 1, 1000);
+    let other = mint_and_borrow(&client, &owner, &borrower, 2, 1000);
+    assert_eq!(client.action_nonce(&loan_id), 1);
+
+    client.repay_loan(&loan_id, &200, &borrower);
+    assert_eq!(client.action_nonce(&loan_id), 2);
+    client.repay_loan(&loan_id, &200, &borrower);
+    assert_eq!(client.action_nonce(&loan_id), 3);
+    client.repay_loan(&loan_id, &600, &borrower);
+    assert_eq!(client.action_nonce(&loan_id), 5);
+    client.reclaim_collateral(&loan_id, &1, &borrower);
+    assert_eq!(client.action_nonce(&loan_id), 6);
+    assert_eq!(client.action_nonce(&other), 1);
 }